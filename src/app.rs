@@ -1,56 +1,157 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
 use app_units::Au;
 use webrender::Transaction;
-use webrender_api::{Epoch, PipelineId, RenderReasons};
-use winit::{application::ApplicationHandler, dpi::{LogicalSize, PhysicalPosition}, event::{MouseButton, WindowEvent}, event_loop::ActiveEventLoop};
+use webrender_api::{Epoch, ExternalScrollId, PipelineId, RenderReasons, SampledScrollOffset};
+use winit::{
+    application::ApplicationHandler,
+    dpi::{LogicalSize, PhysicalPosition},
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::ActiveEventLoop,
+    keyboard::{Key, ModifiersState, NamedKey}
+};
 
 use crate::{
+    buffer::{wrap::Wrap, Buffer},
+    clipboard::{Clipboard, ClipboardKind, MemoryClipboard, SystemClipboard},
     compositor::Compositor,
+    editor::{Command, Editor, Movement},
+    highlight::{load_syntaxes, plain_text_syntaxes, Highlight, SyntaxHighlighter},
     layout::{
-        context::LayoutContext, display_list::DisplayListBuilder, widget::{layout_root, Block, FontCache, Text, TextStyle}, Point, Rect, Size
-    }
+        context::LayoutContext,
+        display_list::DisplayListBuilder,
+        fragment::BoxFragment,
+        gutter::{Gutter, LineNumbers, Marker, MarkerLane},
+        widget::{layout_root, Block, Direction, Text, TextStyle, Widget, WidgetEvent},
+        Point, Rect, Size
+    },
+    syntax::{language::language_for_path, SyntaxTree},
+    theme::{theme_paths, Theme},
 };
 
 const WIDTH: f32 = 1024.0;
 const HEIGHT: f32 = 768.0;
+const LINE_HEIGHT: f32 = 24.0;
+/// Longest delay between clicks that still counts as a double or triple click.
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(500);
+/// How far, in physical pixels, the pointer may move between multi-clicks.
+const MULTI_CLICK_DISTANCE: f64 = 4.0;
+/// Lines scrolled by one step of a mouse wheel.
+const WHEEL_LINES: f32 = 3.0;
+const DOCUMENT_SCROLL_ID: u64 = 1;
+/// Scrolls the gutter up and down with the document, but never sideways.
+const GUTTER_SCROLL_ID: u64 = 2;
+/// Marks a folded block in the gutter.
+const FOLDED_MARKER: char = '\u{25b8}';
+/// Factor one step of zooming in scales text by.
+const ZOOM_STEP: f32 = 1.1;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 5.0;
 
 pub struct State {
     compositor: Compositor,
     cursor_position: PhysicalPosition<f64>,
+    modifiers: ModifiersState,
     layout_context: LayoutContext,
-    root_pipeline: PipelineId
+    root_pipeline: PipelineId,
+    /// The fragments of the last layout, used for hit-testing.
+    fragment_tree: Option<BoxFragment>,
+    /// Whether the left button is held down over the text.
+    dragging: bool,
+    last_click: Option<(Instant, PhysicalPosition<f64>)>,
+    click_count: u32,
+    /// The window title last set, to only update it when it changes.
+    title: String,
+    /// Whether the next redraw has to lay out and build a new display list,
+    /// rather than render the current one.
+    needs_layout: bool,
+    /// Whether the next layout should scroll the caret into view.
+    reveal_caret: bool,
+    /// How far the document is scrolled.
+    scroll_offset: Point<Au>,
+    /// The part of the document the last layout shaped. Scrolling outside
+    /// it needs a new layout.
+    laid_out: Range<Au>,
+    /// Factor the text is scaled by.
+    zoom: f32,
+    /// How the gutter numbers lines, toggled with Alt+N.
+    line_numbers: LineNumbers,
 }
 
 pub struct App {
     state: Option<State>,
-    font_cache: FontCache,
     layout_root: Block,
+    editor: Editor,
+    clipboard: Box<dyn Clipboard>,
+    /// Parse tree of the buffer, if there is a bundled grammar for it.
+    syntax_tree: Option<Rc<RefCell<SyntaxTree>>>,
+    theme: Theme,
 }
 
 impl App {
-    pub fn new() -> Self {
-        let font_cache = FontCache::new();
+    pub fn new(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let theme = Theme::light();
 
-        let text = "Hello, world!";
         let text_style = TextStyle {
             font_family: "Fira Code".to_owned(),
             font_size: 16.0,
             font_style: ttf_parser::Style::Normal,
             font_weight: ttf_parser::Weight::Normal,
-            line_height: 24.0,
-            color: 0x00000000,
+            line_height: LINE_HEIGHT,
+            font_features: Vec::new(),
+            color: theme.foreground,
         };
-        let widget = Text {
-            text: text.to_owned(),
-            style: text_style,
+        let mut buffer = match path {
+            Some(path) => Buffer::open(path)?,
+            None => Buffer::new(),
+        };
+        // How many undo steps are kept can be set in the environment.
+        if let Some(limit) = std::env::var("WEDIT_HISTORY_LIMIT").ok().and_then(|limit| limit.parse().ok()) {
+            buffer.history_mut().set_limit(limit);
+        }
+        // Prefer a bundled tree-sitter grammar, then a grammar from disk.
+        let syntax_tree = buffer
+            .path()
+            .and_then(language_for_path)
+            .and_then(|language| report(SyntaxTree::new(language, &buffer, &theme)))
+            .map(|tree| Rc::new(RefCell::new(tree)));
+        let highlighter: Box<dyn Highlight> = match &syntax_tree {
+            Some(tree) => Box::new(tree.clone()),
+            None => {
+                let syntaxes = load_syntaxes().unwrap_or_else(|err| {
+                    eprintln!("wedit: {err:#}, falling back to plain text");
+                    plain_text_syntaxes()
+                });
+                Box::new(SyntaxHighlighter::new(Rc::new(syntaxes), &buffer, &theme))
+            }
         };
+        let buffer = Rc::new(RefCell::new(buffer));
+        let widget = Text::new(buffer.clone(), text_style);
+        widget.set_highlighter(highlighter);
+        let gutter = Gutter::new(&widget, &theme);
 
-        let root = Block::new(vec![widget]);
+        let root = Block::new(Direction::Row, vec![Box::new(gutter), Box::new(widget)]);
 
-        Self {
+        let clipboard: Box<dyn Clipboard> = match SystemClipboard::new() {
+            Ok(clipboard) => Box::new(clipboard),
+            Err(err) => {
+                eprintln!("wedit: {err:#}, copied text will only be available in this window");
+                Box::new(MemoryClipboard::default())
+            }
+        };
+
+        Ok(Self {
             state: None,
-            font_cache,
             layout_root: root,
-        }
+            editor: Editor::new(buffer),
+            clipboard,
+            syntax_tree,
+            theme,
+        })
     }
 
     pub fn run(&mut self) {
@@ -63,12 +164,25 @@ impl App {
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         let size = LogicalSize { width: WIDTH, height: HEIGHT };
-        let (compositor, api) = Compositor::init(event_loop, size).unwrap();
+        let title = window_title(&self.editor.buffer().borrow());
+        let (compositor, api) = Compositor::init(event_loop, size, &title, self.theme.background).unwrap();
         let state = State {
             compositor,
             cursor_position: PhysicalPosition::new(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
             layout_context: LayoutContext::new(api, Size { width: Au::from_f32_px(WIDTH), height: Au::from_f32_px(HEIGHT) }),
-            root_pipeline: PipelineId(0, 0)
+            root_pipeline: PipelineId(0, 0),
+            fragment_tree: None,
+            dragging: false,
+            last_click: None,
+            click_count: 0,
+            title,
+            needs_layout: true,
+            reveal_caret: false,
+            scroll_offset: Point::default(),
+            laid_out: Au(0)..Au(0),
+            zoom: 1.0,
+            line_numbers: LineNumbers::Absolute,
         };
         self.state = Some(state);
     }
@@ -82,8 +196,20 @@ impl ApplicationHandler for App {
             let State {
                 compositor,
                 cursor_position,
+                modifiers,
                 layout_context,
-                root_pipeline
+                root_pipeline,
+                fragment_tree,
+                dragging,
+                last_click,
+                click_count,
+                title,
+                needs_layout,
+                reveal_caret,
+                scroll_offset,
+                laid_out,
+                zoom,
+                line_numbers,
             } = self.state.as_mut().unwrap();
             let size = compositor.device_size();
             let scale_factor = compositor.scale_factor() as f32;
@@ -99,40 +225,466 @@ impl ApplicationHandler for App {
                 }
                 WindowEvent::Resized(size) => {
                     compositor.resize(size);
+                    *needs_layout = true;
+                    compositor.request_redraw();
+                }
+                WindowEvent::ScaleFactorChanged { .. } => {
+                    // Layout is in logical pixels, but the viewport and the
+                    // font instances glyphs are rasterized with are not.
+                    *needs_layout = true;
+                    compositor.request_redraw();
+                }
+                WindowEvent::ModifiersChanged(new_modifiers) => {
+                    *modifiers = new_modifiers.state();
+                }
+                WindowEvent::KeyboardInput { event, .. } => {
+                    if event.state != ElementState::Pressed {
+                        return;
+                    }
+                    let clipboard = self.clipboard.as_mut();
+                    let changed = if modifiers.control_key() && is_character(&event, "s") {
+                        report(self.editor.buffer().borrow_mut().save());
+                        false
+                    } else if modifiers.control_key() && is_character(&event, "c") {
+                        report(self.editor.copy(clipboard, ClipboardKind::Clipboard));
+                        false
+                    } else if modifiers.control_key() && is_character(&event, "x") {
+                        report(self.editor.cut(clipboard)).unwrap_or(false)
+                    } else if modifiers.control_key() && is_character(&event, "v") {
+                        self.editor.paste(clipboard, ClipboardKind::Clipboard)
+                    } else if modifiers.control_key() && let Some(new_zoom) = zoom_key(&event, *zoom) {
+                        // Keep the same part of the document in view.
+                        let factor = new_zoom / *zoom;
+                        *scroll_offset = Point::new(scroll_offset.i.scale_by(factor), scroll_offset.b.scale_by(factor));
+                        *zoom = new_zoom;
+                        self.layout_root.handle_event(&WidgetEvent::Zoom(new_zoom));
+                        *needs_layout = true;
+                        compositor.request_redraw();
+                        false
+                    } else if modifiers.control_key() && is_character(&event, "t") {
+                        // Themes are looked up again, to pick up new files.
+                        let themes = available_themes();
+                        let current = themes.iter().position(|theme| theme.name == self.theme.name);
+                        let next = current.map_or(0, |i| (i + 1) % themes.len());
+                        self.theme = themes.into_iter().nth(next).expect("built-in themes");
+                        self.layout_root.handle_event(&WidgetEvent::Theme(&self.theme));
+                        compositor.set_clear_color(self.theme.background);
+                        *needs_layout = true;
+                        compositor.request_redraw();
+                        false
+                    } else if modifiers.alt_key() && modifiers.shift_key() &&
+                        let Some(grow) = expand_key(&event) &&
+                        let Some(tree) = &self.syntax_tree
+                    {
+                        tree.borrow_mut().update(&self.editor.buffer().borrow());
+                        if grow {
+                            self.editor.expand_selection(&tree.borrow())
+                        } else {
+                            self.editor.shrink_selection()
+                        }
+                    } else if modifiers.control_key() && modifiers.shift_key() &&
+                        (is_character(&event, "[") || is_character(&event, "{")) &&
+                        let Some(tree) = &self.syntax_tree
+                    {
+                        let buffer = self.editor.buffer().borrow();
+                        let mut tree = tree.borrow_mut();
+                        tree.update(&buffer);
+                        let line = buffer.char_to_line(self.editor.cursor());
+                        tree.toggle_fold(&buffer.snapshot(), line)
+                    } else if modifiers.alt_key() && is_character(&event, "n") {
+                        *line_numbers = match line_numbers {
+                            LineNumbers::Absolute => LineNumbers::Relative,
+                            LineNumbers::Relative => LineNumbers::Absolute,
+                        };
+                        self.layout_root.handle_event(&WidgetEvent::LineNumbers(*line_numbers));
+                        *needs_layout = true;
+                        compositor.request_redraw();
+                        false
+                    } else if modifiers.alt_key() && is_character(&event, "z") {
+                        let buffer = self.editor.buffer();
+                        let mut settings = buffer.borrow().wrap();
+                        settings.wrap = if settings.wrap == Wrap::None { Wrap::Window } else { Wrap::None };
+                        buffer.borrow_mut().set_wrap(settings);
+                        self.layout_root.handle_event(&WidgetEvent::Wrap(buffer));
+                        true
+                    } else if let Some(command) = key_command(&event, *modifiers) {
+                        let page_lines = viewport_size.height.to_f32_px() / (LINE_HEIGHT * *zoom);
+                        self.editor.set_page_lines(page_lines as usize);
+                        let is_select = matches!(command, Command::Select(_));
+                        let changed = self.editor.execute(command);
+                        if changed && is_select {
+                            report(self.editor.copy(clipboard, ClipboardKind::Primary));
+                        }
+                        changed
+                    } else {
+                        false
+                    };
+                    if changed {
+                        *needs_layout = true;
+                        *reveal_caret = true;
+                        compositor.request_redraw();
+                    }
                 }
                 WindowEvent::CursorMoved { device_id, position} => {
                     *cursor_position = position;
+                    if *dragging {
+                        let point = document_point(fragment_tree.as_ref(), to_layout_point(position, scale_factor), *scroll_offset);
+                        let offset = fragment_tree.as_ref().and_then(|tree| tree.hit_test(point));
+                        if let Some(offset) = offset {
+                            let selection = self.editor.selection();
+                            self.editor.select_to(offset);
+                            if self.editor.selection() != selection {
+                                *needs_layout = true;
+                                *reveal_caret = true;
+                                compositor.request_redraw();
+                            }
+                        }
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    // Positive deltas scroll up and left, towards the start of the document.
+                    let (delta_i, delta_b) = match delta {
+                        MouseScrollDelta::LineDelta(columns, lines) => {
+                            let line_height = LINE_HEIGHT * *zoom;
+                            (columns * WHEEL_LINES * line_height, lines * WHEEL_LINES * line_height)
+                        }
+                        MouseScrollDelta::PixelDelta(position) => {
+                            (position.x as f32 / scale_factor, position.y as f32 / scale_factor)
+                        }
+                    };
+                    // Shift turns a vertical wheel sideways.
+                    let (delta_i, delta_b) = if modifiers.shift_key() { (delta_b, delta_i) } else { (delta_i, delta_b) };
+                    let content_size = fragment_tree.as_ref().map_or(Size::default(), |tree| tree.rect.size);
+                    let offset = Point::new(
+                        clamp_scroll_offset(
+                            scroll_offset.i - Au::from_f32_px(delta_i),
+                            content_size.width,
+                            viewport_size.width
+                        ),
+                        clamp_scroll_offset(
+                            scroll_offset.b - Au::from_f32_px(delta_b),
+                            content_size.height,
+                            viewport_size.height
+                        ),
+                    );
+                    if offset.i != scroll_offset.i || offset.b != scroll_offset.b {
+                        *scroll_offset = offset;
+                        if offset.b < laid_out.start || offset.b + viewport_size.height > laid_out.end {
+                            *needs_layout = true;
+                            compositor.request_redraw();
+                            return;
+                        }
+                        // Only move the existing display list.
+                        let mut txn = Transaction::new();
+                        set_scroll_offset(&mut txn, *root_pipeline, *scroll_offset, scale_factor);
+                        txn.generate_frame(0, RenderReasons::empty());
+                        layout_context.webrender_api.send_transaction(layout_context.document_id, txn);
+                        compositor.request_redraw();
+                    }
+                }
+                WindowEvent::MouseInput { device_id, state, button: MouseButton::Middle } => {
+                    if state != ElementState::Pressed {
+                        return;
+                    }
+                    let point = document_point(fragment_tree.as_ref(), to_layout_point(*cursor_position, scale_factor), *scroll_offset);
+                    let offset = fragment_tree.as_ref().and_then(|tree| tree.hit_test(point));
+                    if let Some(offset) = offset {
+                        self.editor.set_cursor(offset);
+                        self.editor.paste(self.clipboard.as_mut(), ClipboardKind::Primary);
+                        *needs_layout = true;
+                        compositor.request_redraw();
+                    }
                 }
                 WindowEvent::MouseInput { device_id, state, button} => {
-                    if button == MouseButton::Left {
-                        println!("cursor position: {:?}", *cursor_position);
+                    if button != MouseButton::Left {
+                        return;
+                    }
+                    if state == ElementState::Released {
+                        if std::mem::take(dragging) {
+                            report(self.editor.copy(self.clipboard.as_mut(), ClipboardKind::Primary));
+                        }
+                        return;
+                    }
+
+                    let now = Instant::now();
+                    let is_repeat = last_click.is_some_and(|(time, position)| {
+                        now.duration_since(time) < MULTI_CLICK_INTERVAL &&
+                            (position.x - cursor_position.x).abs() <= MULTI_CLICK_DISTANCE &&
+                            (position.y - cursor_position.y).abs() <= MULTI_CLICK_DISTANCE
+                    });
+                    // Clicks cycle through caret, word and line selection.
+                    *click_count = if is_repeat { *click_count % 3 + 1 } else { 1 };
+                    *last_click = Some((now, *cursor_position));
+
+                    let point = document_point(fragment_tree.as_ref(), to_layout_point(*cursor_position, scale_factor), *scroll_offset);
+                    let offset = fragment_tree.as_ref().and_then(|tree| tree.hit_test(point));
+                    if let Some(offset) = offset {
+                        match *click_count {
+                            1 if modifiers.shift_key() => self.editor.select_to(offset),
+                            1 => self.editor.set_cursor(offset),
+                            2 => self.editor.select_word(offset),
+                            _ => self.editor.select_line(offset),
+                        }
+                        *dragging = true;
+                        *needs_layout = true;
+                        compositor.request_redraw();
                     }
                 }
                 WindowEvent::RedrawRequested => {
-                    let root_fragment = layout_root(&self.layout_root, layout_context, viewport_size);
+                    if !std::mem::take(needs_layout) {
+                        compositor.update();
+                        return;
+                    }
+
+                    if let Some(tree) = &self.syntax_tree {
+                        let buffer = self.editor.buffer().borrow();
+                        let snapshot = buffer.snapshot();
+                        let mut tree = tree.borrow_mut();
+                        tree.update(&buffer);
+                        // Never hide the caret.
+                        tree.unfold_line(&snapshot, buffer.char_to_line(self.editor.cursor()));
+                        let folded = tree.folded_lines(&snapshot);
+                        let markers: Vec<Marker> = folded
+                            .iter()
+                            .map(|lines| Marker {
+                                line: lines.start - 1,
+                                symbol: FOLDED_MARKER,
+                                color: self.theme.gutter_foreground,
+                            })
+                            .collect();
+                        let buffer = self.editor.buffer();
+                        self.layout_root.handle_event(&WidgetEvent::Markers { buffer, lane: MarkerLane::Fold, markers: &markers });
+                        self.layout_root.handle_event(&WidgetEvent::HiddenLines { buffer, lines: &folded });
+                    }
+
+                    let reveal = std::mem::take(reveal_caret);
+                    if reveal {
+                        // Scroll to where the caret's line is estimated to be,
+                        // so that it gets laid out.
+                        let buffer = self.editor.buffer();
+                        let line = buffer.borrow().char_to_line(self.editor.cursor());
+                        if let Some(line_top) = self.layout_root.line_offset(buffer, line) {
+                            let line_height = Au::from_f32_px(LINE_HEIGHT * *zoom);
+                            if line_top < scroll_offset.b {
+                                scroll_offset.b = line_top;
+                            } else if line_top + line_height > scroll_offset.b + viewport_size.height {
+                                scroll_offset.b = line_top + line_height - viewport_size.height;
+                            }
+                        }
+                    }
+
+                    self.layout_root.handle_event(&WidgetEvent::Caret {
+                        buffer: self.editor.buffer(),
+                        position: self.editor.cursor(),
+                    });
+
+                    // Shape a viewport's worth of lines either side of the
+                    // visible ones, so that short scrolls need no new layout.
+                    let margin = viewport_size.height;
+                    *laid_out = (scroll_offset.b - margin).max(Au(0))..scroll_offset.b + viewport_size.height + margin;
+                    let root_fragment = layout_root(&self.layout_root, layout_context, viewport_size, laid_out.clone());
+                    let content_size = root_fragment.rect.size;
+                    let gutter_width = root_fragment.gutter().map_or(Au(0), |gutter| gutter.rect.size.width);
+                    if reveal &&
+                        let Some(caret_rect) = root_fragment.caret_rect(&self.editor.caret())
+                    {
+                        let caret_bottom = caret_rect.origin.b + caret_rect.size.height;
+                        if caret_rect.origin.b < scroll_offset.b {
+                            scroll_offset.b = caret_rect.origin.b;
+                        } else if caret_bottom > scroll_offset.b + viewport_size.height {
+                            scroll_offset.b = caret_bottom - viewport_size.height;
+                        }
+                        // Text scrolled sideways passes under the gutter.
+                        let caret_right = caret_rect.origin.i + caret_rect.size.width;
+                        if caret_rect.origin.i < scroll_offset.i + gutter_width {
+                            scroll_offset.i = caret_rect.origin.i - gutter_width;
+                        } else if caret_right > scroll_offset.i + viewport_size.width {
+                            scroll_offset.i = caret_right - viewport_size.width;
+                        }
+                    }
+                    scroll_offset.i = clamp_scroll_offset(scroll_offset.i, content_size.width, viewport_size.width);
+                    scroll_offset.b = clamp_scroll_offset(scroll_offset.b, content_size.height, viewport_size.height);
+
                     let rect = Rect {
                         origin: Point::new(Au(0), Au(0)),
                         size: viewport_size
                     };
+                    let content_size = Size {
+                        width: content_size.width.max(viewport_size.width),
+                        height: content_size.height.max(viewport_size.height),
+                    };
+                    let scroll_id = ExternalScrollId(DOCUMENT_SCROLL_ID, *root_pipeline);
                     let mut dl_builder = DisplayListBuilder::new(
                         scale_factor,
                         *root_pipeline,
                         layout_context
                     );
+                    dl_builder.selection = self.editor.selection().range();
+                    dl_builder.selection_color = self.theme.selection;
+                    dl_builder.caret_color = self.theme.caret;
+                    dl_builder.gutter_background = self.theme.gutter_background;
+                    dl_builder.gutter_scroll_id = Some(ExternalScrollId(GUTTER_SCROLL_ID, *root_pipeline));
                     dl_builder.wr.begin();
+                    dl_builder.push_scroll_frame(scroll_id, rect, content_size);
 
-                    root_fragment.build_display_list(&mut dl_builder, rect);
+                    self.layout_root.paint(&root_fragment, &mut dl_builder, rect);
+                    dl_builder.push_caret(&root_fragment, &self.editor.caret(), rect);
+                    dl_builder.pop_scroll_frame();
 
                     let mut txn = Transaction::new();
                     txn.set_display_list(Epoch(0), dl_builder.wr.end());
                     txn.set_root_pipeline(*root_pipeline);
+                    set_scroll_offset(&mut txn, *root_pipeline, *scroll_offset, scale_factor);
                     txn.generate_frame(0, RenderReasons::empty());
 
                     layout_context.webrender_api.send_transaction(layout_context.document_id, txn);
+                    layout_context.evict_font_instances();
 
                     compositor.update();
+                    *fragment_tree = Some(root_fragment);
                 }
                 _ => {}
             }
+
+            let new_title = window_title(&self.editor.buffer().borrow());
+            if *title != new_title {
+                compositor.set_title(&new_title);
+                *title = new_title;
+            }
+    }
+}
+
+/// Convert a position in the window to layout coordinates.
+fn to_layout_point(position: PhysicalPosition<f64>, scale_factor: f32) -> Point<Au> {
+    Point::new(
+        Au::from_f64_px(position.x / scale_factor as f64),
+        Au::from_f64_px(position.y / scale_factor as f64)
+    )
+}
+
+/// Where a point in layout coordinates is in the scrolled document. Gutters
+/// only scroll vertically, so points over them keep clear of the text scrolled
+/// sideways under them, and hit-test as the start of their line.
+fn document_point(fragment_tree: Option<&BoxFragment>, point: Point<Au>, scroll_offset: Point<Au>) -> Point<Au> {
+    let gutter_point = Point::new(point.i, point.b + scroll_offset.b);
+    if fragment_tree.is_some_and(|tree| tree.is_over_gutter(gutter_point)) {
+        gutter_point
+    } else {
+        point + scroll_offset
     }
-}
\ No newline at end of file
+}
+
+/// Keep a scroll offset along one axis between the start of the document and
+/// the point where its end reaches the edge of the viewport.
+fn clamp_scroll_offset(offset: Au, content_len: Au, viewport_len: Au) -> Au {
+    let max = (content_len - viewport_len).max(Au(0));
+    offset.max(Au(0)).min(max)
+}
+
+fn set_scroll_offset(txn: &mut Transaction, pipeline_id: PipelineId, offset: Point<Au>, scale_factor: f32) {
+    let sampled = |offset: Point<Au>| SampledScrollOffset {
+        offset: offset.to_layout(scale_factor).to_vector(),
+        generation: 0,
+    };
+    txn.set_scroll_offsets(ExternalScrollId(DOCUMENT_SCROLL_ID, pipeline_id), vec![sampled(offset)]);
+    txn.set_scroll_offsets(ExternalScrollId(GUTTER_SCROLL_ID, pipeline_id), vec![sampled(Point::new(Au(0), offset.b))]);
+}
+
+/// Print an error that the user should know about but that is not fatal.
+fn report<T>(result: anyhow::Result<T>) -> Option<T> {
+    result.map_err(|err| eprintln!("wedit: {err:#}")).ok()
+}
+
+/// The built-in themes, followed by those loaded from theme files.
+fn available_themes() -> Vec<Theme> {
+    let loaded = theme_paths().into_iter().filter_map(|path| report(Theme::load(&path)));
+    [Theme::light(), Theme::dark()].into_iter().chain(loaded).collect()
+}
+
+/// The window title: the file name, marked when there are unsaved changes.
+fn window_title(buffer: &Buffer) -> String {
+    let name = buffer
+        .path()
+        .and_then(|path| path.file_name())
+        .map_or("Untitled".into(), |name| name.to_string_lossy());
+    let marker = if buffer.is_modified() { " \u{2022}" } else { "" };
+    format!("{name}{marker} - Wedit")
+}
+
+/// Whether a key press is for the character `c`, ignoring case.
+fn is_character(event: &KeyEvent, c: &str) -> bool {
+    matches!(&event.logical_key, Key::Character(key) if key.eq_ignore_ascii_case(c))
+}
+
+/// Whether Alt+Shift+Right grows the selection, or Alt+Shift+Left shrinks it.
+fn expand_key(event: &KeyEvent) -> Option<bool> {
+    match event.logical_key {
+        Key::Named(NamedKey::ArrowRight) => Some(true),
+        Key::Named(NamedKey::ArrowLeft) => Some(false),
+        _ => None,
+    }
+}
+
+/// The zoom that Ctrl+= or Ctrl++, Ctrl+- and Ctrl+0 change `zoom` to.
+fn zoom_key(event: &KeyEvent, zoom: f32) -> Option<f32> {
+    let zoom = if is_character(event, "=") || is_character(event, "+") {
+        zoom * ZOOM_STEP
+    } else if is_character(event, "-") {
+        zoom / ZOOM_STEP
+    } else if is_character(event, "0") {
+        1.0
+    } else {
+        return None;
+    };
+    Some(zoom.clamp(MIN_ZOOM, MAX_ZOOM))
+}
+
+/// Map a key press to an editing command.
+fn key_command(event: &KeyEvent, modifiers: ModifiersState) -> Option<Command> {
+    if let Key::Named(key) = event.logical_key && let Some(movement) = key_movement(key) {
+        return if modifiers.shift_key() {
+            Some(Command::Select(movement))
+        } else {
+            Some(Command::Move(movement))
+        };
+    }
+
+    let command = match &event.logical_key {
+        Key::Named(NamedKey::Backspace) => Command::Backspace,
+        Key::Named(NamedKey::Delete) => Command::Delete,
+        Key::Named(NamedKey::Enter) => Command::Newline,
+        Key::Named(NamedKey::Tab) => Command::Tab,
+        Key::Character(c) if modifiers.control_key() => {
+            match c.to_lowercase().as_str() {
+                "z" if modifiers.shift_key() => Command::Redo,
+                "z" => Command::Undo,
+                "y" => Command::Redo,
+                _ => return None,
+            }
+        }
+        _ => {
+            // Chords are shortcuts, not text.
+            if modifiers.control_key() || modifiers.alt_key() || modifiers.super_key() {
+                return None;
+            }
+            let text: String = event.text.as_ref()?.chars().filter(|c| !c.is_control()).collect();
+            Command::Insert(text)
+        }
+    };
+    Some(command)
+}
+
+fn key_movement(key: NamedKey) -> Option<Movement> {
+    let movement = match key {
+        NamedKey::ArrowLeft => Movement::Left,
+        NamedKey::ArrowRight => Movement::Right,
+        NamedKey::ArrowUp => Movement::Up,
+        NamedKey::ArrowDown => Movement::Down,
+        NamedKey::Home => Movement::LineStart,
+        NamedKey::End => Movement::LineEnd,
+        NamedKey::PageUp => Movement::PageUp,
+        NamedKey::PageDown => Movement::PageDown,
+        _ => return None,
+    };
+    Some(movement)
+}
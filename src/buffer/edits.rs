@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+/// Number of edits kept for views to catch up on. A view that falls further
+/// behind starts over from the current contents.
+const EDIT_LOG_LIMIT: usize = 1024;
+
+/// A place in the text, as a line and a byte offset into it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a change replaced text, in bytes and lines, before and after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start: TextPosition,
+    pub old_end: TextPosition,
+    pub new_end: TextPosition,
+}
+
+/// The edits most recently made to a buffer, for views that keep state
+/// derived from its text, such as syntax highlighting, to update only what
+/// changed.
+///
+/// Every edit bumps the revision by one.
+#[derive(Default)]
+pub struct EditLog {
+    edits: VecDeque<Edit>,
+    /// Revision of the buffer before the oldest edit kept.
+    base: u64,
+}
+
+impl EditLog {
+    /// Revision of the buffer after the latest edit.
+    pub fn revision(&self) -> u64 {
+        self.base + self.edits.len() as u64
+    }
+
+    pub fn push(&mut self, edit: Edit) {
+        if self.edits.len() == EDIT_LOG_LIMIT {
+            self.edits.pop_front();
+            self.base += 1;
+        }
+        self.edits.push_back(edit);
+    }
+
+    /// The edits made since `revision`, oldest first, or `None` if some of
+    /// them are no longer kept.
+    pub fn since(&self, revision: u64) -> Option<impl Iterator<Item = &Edit>> {
+        let skip = revision.checked_sub(self.base)?;
+        if skip > self.edits.len() as u64 {
+            return None;
+        }
+        Some(self.edits.range(skip as usize..))
+    }
+}
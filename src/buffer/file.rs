@@ -0,0 +1,81 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ropey::Rope;
+
+use super::Buffer;
+
+impl Buffer {
+    /// Load the file at `path` into a new buffer. A file that does not exist
+    /// yet gives an empty buffer, and is created on the first save.
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Buffer> {
+        let path = path.into();
+        let rope = match File::open(&path) {
+            Ok(file) => Rope::from_reader(BufReader::new(file))
+                .with_context(|| format!("failed to read {}", path.display()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Rope::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to open {}", path.display()));
+            }
+        };
+        Ok(Buffer {
+            rope,
+            path: Some(path),
+            ..Buffer::default()
+        })
+    }
+
+    /// Write the buffer back to its file.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let path = self.path.clone().context("the buffer has no file to save to")?;
+        write_atomically(&path, |writer| self.rope.write_to(writer))
+            .with_context(|| format!("failed to save {}", path.display()))?;
+        self.saved_id = self.history.current_id();
+        // Typing after a save starts a new undo step, so that undoing it gets
+        // back to the saved contents.
+        self.history.seal();
+        Ok(())
+    }
+}
+
+/// Write a file through a temporary file in the same directory, which is then
+/// renamed over it. A failed write never leaves a truncated file behind.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    // Replace the file a symlink points to rather than the symlink itself.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = write_file(&temp_path, &path, write).and_then(|()| fs::rename(&temp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn write_file(
+    path: &Path,
+    permissions_from: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let file = File::create(path)?;
+    if let Ok(metadata) = fs::metadata(permissions_from) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    writer.flush()?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()
+}
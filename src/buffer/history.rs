@@ -0,0 +1,235 @@
+use std::collections::VecDeque;
+
+use crate::editor::Selection;
+
+use super::Change;
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+/// What caused an edit. Runs of typing or deleting are undone together, a line
+/// at a time, while every other kind of edit is its own undo step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditKind {
+    Typing,
+    Deleting,
+    Paste,
+    Cut,
+    Other,
+}
+
+impl EditKind {
+    fn coalesces(self) -> bool {
+        matches!(self, EditKind::Typing | EditKind::Deleting)
+    }
+}
+
+/// One undo step: the changes it made, in order, and the selection on either side.
+struct Transaction {
+    /// Identifies the state of the buffer after this step.
+    id: u64,
+    kind: EditKind,
+    changes: Vec<Change>,
+    selection_before: Selection,
+    selection_after: Selection,
+}
+
+/// Undo and redo stacks for a buffer.
+pub struct History {
+    undo: VecDeque<Transaction>,
+    redo: Vec<Transaction>,
+    /// Maximum number of undo steps kept; the oldest are dropped first.
+    limit: usize,
+    /// Set to stop the next edit from joining the last undo step.
+    sealed: bool,
+    next_id: u64,
+    /// The id of the state before the oldest undo step.
+    base_id: u64,
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+            sealed: false,
+            next_id: 1,
+            base_id: 0,
+        }
+    }
+
+    /// An id for the current state of the buffer, which comes back when undo or
+    /// redo return to that state. The unedited buffer is 0.
+    pub fn current_id(&self) -> u64 {
+        self.undo.back().map_or(self.base_id, |transaction| transaction.id)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        if limit == 0 {
+            // Nothing could be redone without an undo step to keep it in.
+            self.redo.clear();
+        }
+        self.trim();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Make the next edit start a new undo step even if it could coalesce.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    pub fn record(&mut self, change: Change, kind: EditKind, selection_before: Selection, selection_after: Selection) {
+        self.redo.clear();
+
+        // Keep coalescing only while the caret stays where the last edit left
+        // it, and start again at each new line.
+        let sealed = std::mem::take(&mut self.sealed);
+        let breaks_line = change.inserted.contains('\n');
+        if !sealed && !breaks_line && kind.coalesces() &&
+            let Some(last) = self.undo.back_mut() &&
+            last.kind == kind && last.selection_after == selection_before
+        {
+            last.changes.push(change);
+            last.selection_after = selection_after;
+            return;
+        }
+
+        self.undo.push_back(Transaction {
+            id: self.next_id,
+            kind,
+            changes: vec![change],
+            selection_before,
+            selection_after,
+        });
+        self.next_id += 1;
+        self.trim();
+    }
+
+    /// Drop the oldest undo steps beyond the limit.
+    fn trim(&mut self) {
+        while self.undo.len() > self.limit {
+            if let Some(transaction) = self.undo.pop_front() {
+                self.base_id = transaction.id;
+            }
+        }
+    }
+
+    /// Take the changes of the last undo step, to be reverted in reverse order.
+    pub(super) fn undo(&mut self) -> Option<(&[Change], Selection)> {
+        let transaction = self.undo.pop_back()?;
+        self.sealed = true;
+        self.redo.push(transaction);
+        let transaction = self.redo.last().unwrap();
+        Some((&transaction.changes, transaction.selection_before))
+    }
+
+    /// Take the changes of the last undone step, to be applied again in order.
+    pub(super) fn redo(&mut self) -> Option<(&[Change], Selection)> {
+        let transaction = self.redo.pop()?;
+        self.sealed = true;
+        self.undo.push_back(transaction);
+        self.trim();
+        let transaction = self.undo.back().unwrap();
+        Some((&transaction.changes, transaction.selection_after))
+    }
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::Buffer;
+
+    use super::*;
+
+    /// Type `text` a char at a time at the end of the buffer.
+    fn type_text(buffer: &mut Buffer, text: &str) {
+        for c in text.chars() {
+            let end = buffer.len_chars();
+            buffer.edit(end..end, &c.to_string(), EditKind::Typing, Selection::caret(end), Selection::caret(end + 1));
+        }
+    }
+
+    fn undo_all(buffer: &mut Buffer) -> Vec<String> {
+        let mut states = Vec::new();
+        while buffer.undo().is_some() {
+            states.push(buffer.to_string());
+        }
+        states
+    }
+
+    #[test]
+    fn typing_coalesces_a_line_at_a_time() {
+        let mut buffer = Buffer::new();
+        type_text(&mut buffer, "let a;\nlet b;");
+        assert_eq!(undo_all(&mut buffer), ["let a;", ""]);
+    }
+
+    #[test]
+    fn other_edits_and_moving_the_caret_start_new_steps() {
+        let mut buffer = Buffer::new();
+        type_text(&mut buffer, "ab");
+        buffer.edit(2..2, "cd", EditKind::Paste, Selection::caret(2), Selection::caret(4));
+        type_text(&mut buffer, "e");
+        // Typing somewhere else than where the caret was left.
+        buffer.edit(0..0, "f", EditKind::Typing, Selection::caret(0), Selection::caret(1));
+        type_text(&mut buffer, "g");
+        assert_eq!(buffer.to_string(), "fabcdeg");
+        assert_eq!(undo_all(&mut buffer), ["fabcde", "abcde", "abcd", "ab", ""]);
+    }
+
+    #[test]
+    fn set_limit_drops_the_oldest_steps() {
+        let mut buffer = Buffer::new();
+        type_text(&mut buffer, "a\nb\nc\nd");
+        buffer.history_mut().set_limit(2);
+        assert_eq!(buffer.history().limit(), 2);
+        assert_eq!(undo_all(&mut buffer), ["a\nb\nc", "a\nb"]);
+
+        // Redo steps count towards the limit too.
+        buffer.redo();
+        buffer.history_mut().set_limit(0);
+        assert!(!buffer.history().can_undo() && !buffer.history().can_redo());
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_state_is_unmodified() {
+        let mut buffer = Buffer::new();
+        type_text(&mut buffer, "a");
+        assert!(buffer.is_modified());
+        buffer.undo();
+        assert!(!buffer.is_modified());
+        buffer.redo();
+        assert!(buffer.is_modified());
+
+        let path = std::env::temp_dir().join(format!("wedit-history-test-{}", std::process::id()));
+        let mut buffer = Buffer::open(&path).unwrap();
+        type_text(&mut buffer, "a");
+        buffer.save().unwrap();
+        type_text(&mut buffer, "b");
+        std::fs::remove_file(&path).unwrap();
+        assert!(buffer.is_modified());
+        buffer.undo();
+        assert!(!buffer.is_modified());
+        buffer.undo();
+        assert!(buffer.is_modified());
+        buffer.redo();
+        assert!(!buffer.is_modified());
+    }
+}
@@ -0,0 +1,408 @@
+pub mod edits;
+mod file;
+pub mod history;
+pub mod wrap;
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use ropey::{Rope, RopeSlice};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use crate::editor::Selection;
+
+use self::edits::{Edit, EditLog, TextPosition};
+use self::history::{EditKind, History};
+use self::wrap::WrapSettings;
+
+/// An editable text document backed by a rope.
+///
+/// Positions are char indices unless the method name says otherwise.
+#[derive(Default)]
+pub struct Buffer {
+    rope: Rope,
+    history: History,
+    /// The file this buffer is loaded from and saved to.
+    path: Option<PathBuf>,
+    /// History id of the contents last written to `path`.
+    saved_id: u64,
+    wrap: WrapSettings,
+    /// Where recent changes were made, for views to catch up with.
+    edits: EditLog,
+}
+
+/// A replacement of the chars starting at `start`.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Change {
+    /// The change that undoes this one.
+    pub fn inverse(&self) -> Change {
+        Change {
+            start: self.start,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.rope.len_bytes()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rope.len_bytes() == 0
+    }
+
+    /// Insert `text` at `char_idx` as an undo step of its own, with the caret
+    /// before it to undo to and after it to redo to.
+    pub fn insert(&mut self, char_idx: usize, text: &str) {
+        let selection_after = Selection::caret(char_idx + text.chars().count());
+        self.edit(char_idx..char_idx, text, EditKind::Other, Selection::caret(char_idx), selection_after);
+    }
+
+    /// Remove the chars in `char_range` as an undo step of its own.
+    pub fn remove(&mut self, char_range: Range<usize>) {
+        let selection_before = Selection { anchor: char_range.start, head: char_range.end };
+        let selection_after = Selection::caret(char_range.start);
+        self.edit(char_range, "", EditKind::Other, selection_before, selection_after);
+    }
+
+    pub fn insert_at_byte(&mut self, byte_idx: usize, text: &str) {
+        let char_idx = self.rope.byte_to_char(byte_idx);
+        self.insert(char_idx, text);
+    }
+
+    pub fn remove_bytes(&mut self, byte_range: Range<usize>) {
+        let start = self.rope.byte_to_char(byte_range.start);
+        let end = self.rope.byte_to_char(byte_range.end);
+        self.remove(start..end);
+    }
+
+    /// Replace `char_range` with `text` as one undoable edit.
+    ///
+    /// The selections are what undo and redo restore around the edit.
+    pub fn edit(
+        &mut self,
+        char_range: Range<usize>,
+        text: &str,
+        kind: EditKind,
+        selection_before: Selection,
+        selection_after: Selection,
+    ) {
+        let change = Change {
+            start: char_range.start,
+            removed: self.rope.slice(char_range).to_string(),
+            inserted: text.to_owned(),
+        };
+        apply(&mut self.rope, &mut self.edits, &change);
+        self.history.record(change, kind, selection_before, selection_after);
+    }
+
+    /// Revert the last undo step, returning the selection from before it.
+    pub fn undo(&mut self) -> Option<Selection> {
+        let (changes, selection) = self.history.undo()?;
+        for change in changes.iter().rev() {
+            apply(&mut self.rope, &mut self.edits, &change.inverse());
+        }
+        Some(selection)
+    }
+
+    /// Apply the last undone step again, returning the selection from after it.
+    pub fn redo(&mut self) -> Option<Selection> {
+        let (changes, selection) = self.history.redo()?;
+        for change in changes {
+            apply(&mut self.rope, &mut self.edits, change);
+        }
+        Some(selection)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn wrap(&self) -> WrapSettings {
+        self.wrap
+    }
+
+    pub fn set_wrap(&mut self, wrap: WrapSettings) {
+        self.wrap = wrap;
+    }
+
+    /// Whether the contents differ from what was last loaded or saved.
+    pub fn is_modified(&self) -> bool {
+        self.history.current_id() != self.saved_id
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// Counts the changes made to the contents, for views to tell whether
+    /// they are up to date.
+    pub fn revision(&self) -> u64 {
+        self.edits.revision()
+    }
+
+    /// Where the changes since `revision` were made, oldest first, or `None`
+    /// if the buffer no longer remembers them all.
+    pub fn edits_since(&self, revision: u64) -> Option<impl Iterator<Item = &Edit>> {
+        self.edits.since(revision)
+    }
+
+    pub fn char(&self, char_idx: usize) -> char {
+        self.rope.char(char_idx)
+    }
+
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        self.rope.char_to_byte(char_idx)
+    }
+
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        self.rope.byte_to_char(byte_idx)
+    }
+
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        self.rope.char_to_line(char_idx)
+    }
+
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        self.rope.line_to_char(line_idx)
+    }
+
+    pub fn line_to_byte(&self, line_idx: usize) -> usize {
+        self.rope.line_to_byte(line_idx)
+    }
+
+    /// The line at `line_idx`, including its line break if it has one.
+    pub fn line(&self, line_idx: usize) -> RopeSlice<'_> {
+        self.rope.line(line_idx)
+    }
+
+    /// Number of chars in a line, not counting its line break.
+    pub fn line_len_chars(&self, line_idx: usize) -> usize {
+        line_content(self.rope.line(line_idx)).len_chars()
+    }
+
+    pub fn slice(&self, char_range: Range<usize>) -> RopeSlice<'_> {
+        self.rope.slice(char_range)
+    }
+
+    /// The start of the grapheme cluster before `char_idx`, e.g. of a CRLF or
+    /// of a letter and its combining marks, or 0 at the start.
+    pub fn prev_grapheme_boundary(&self, char_idx: usize) -> usize {
+        let byte_idx = self.rope.char_to_byte(char_idx);
+        let mut cursor = GraphemeCursor::new(byte_idx, self.rope.len_bytes(), true);
+        let (mut chunk, mut chunk_start, ..) = self.rope.chunk_at_byte(byte_idx);
+        loop {
+            match cursor.prev_boundary(chunk, chunk_start) {
+                Ok(boundary) => return self.rope.byte_to_char(boundary.unwrap_or(0)),
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    (chunk, chunk_start, ..) = self.rope.chunk_at_byte(chunk_start - 1);
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_context(&mut cursor, end),
+                // Only the chunks before the cursor are ever asked for.
+                Err(_) => unreachable!(),
+            }
+        }
+    }
+
+    /// The end of the grapheme cluster after `char_idx`, or the end of the
+    /// buffer.
+    pub fn next_grapheme_boundary(&self, char_idx: usize) -> usize {
+        let byte_idx = self.rope.char_to_byte(char_idx);
+        let len_bytes = self.rope.len_bytes();
+        let mut cursor = GraphemeCursor::new(byte_idx, len_bytes, true);
+        let (mut chunk, mut chunk_start, ..) = self.rope.chunk_at_byte(byte_idx);
+        loop {
+            match cursor.next_boundary(chunk, chunk_start) {
+                Ok(boundary) => return self.rope.byte_to_char(boundary.unwrap_or(len_bytes)),
+                Err(GraphemeIncomplete::NextChunk) => {
+                    chunk_start += chunk.len();
+                    chunk = self.rope.chunk_at_byte(chunk_start).0;
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_context(&mut cursor, end),
+                Err(_) => unreachable!(),
+            }
+        }
+    }
+
+    /// Give `cursor` the text of the chunk that ends at byte `end`.
+    fn provide_context(&self, cursor: &mut GraphemeCursor, end: usize) {
+        let (chunk, chunk_start, ..) = self.rope.chunk_at_byte(end - 1);
+        cursor.provide_context(&chunk[..end - chunk_start], chunk_start);
+    }
+
+    /// Take an immutable copy of the current contents.
+    ///
+    /// Ropes share their nodes, so this is O(1) and the snapshot is not
+    /// affected by later edits.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            rope: self.rope.clone(),
+        }
+    }
+}
+
+impl From<&str> for Buffer {
+    fn from(text: &str) -> Buffer {
+        Buffer {
+            rope: Rope::from_str(text),
+            ..Buffer::default()
+        }
+    }
+}
+
+impl std::fmt::Display for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.rope.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only view of a `Buffer` at some point in time.
+#[derive(Clone)]
+pub struct Snapshot {
+    rope: Rope,
+}
+
+impl Snapshot {
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        self.rope.line_to_char(line_idx)
+    }
+
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        self.rope.char_to_line(char_idx)
+    }
+
+    pub fn line_to_byte(&self, line_idx: usize) -> usize {
+        self.rope.line_to_byte(line_idx)
+    }
+
+    pub fn byte_to_line(&self, byte_idx: usize) -> usize {
+        self.rope.byte_to_line(byte_idx)
+    }
+
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        self.rope.char_to_byte(char_idx)
+    }
+
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        self.rope.byte_to_char(byte_idx)
+    }
+
+    pub fn slice(&self, char_range: Range<usize>) -> RopeSlice<'_> {
+        self.rope.slice(char_range)
+    }
+
+    pub fn byte_slice(&self, byte_range: Range<usize>) -> RopeSlice<'_> {
+        self.rope.byte_slice(byte_range)
+    }
+
+    /// The text from `byte_idx` to the end of the chunk of the rope holding
+    /// it, for reading the contents piece by piece. Empty at the end.
+    pub fn chunk_at_byte(&self, byte_idx: usize) -> &str {
+        let (chunk, chunk_start, ..) = self.rope.chunk_at_byte(byte_idx);
+        &chunk[byte_idx - chunk_start..]
+    }
+
+    /// The text of a line without its line break.
+    pub fn line_text(&self, line_idx: usize) -> String {
+        line_content(self.rope.line(line_idx)).to_string()
+    }
+}
+
+fn apply(rope: &mut Rope, edits: &mut EditLog, change: &Change) {
+    let removed_end = change.start + change.removed.chars().count();
+    let start_byte = rope.char_to_byte(change.start);
+    let old_end_byte = rope.char_to_byte(removed_end);
+    let start = text_position(rope, start_byte);
+    let old_end = text_position(rope, old_end_byte);
+    rope.remove(change.start..removed_end);
+    rope.insert(change.start, &change.inserted);
+    let new_end_byte = start_byte + change.inserted.len();
+    edits.push(Edit {
+        start_byte,
+        old_end_byte,
+        new_end_byte,
+        start,
+        old_end,
+        new_end: text_position(rope, new_end_byte),
+    });
+}
+
+fn text_position(rope: &Rope, byte_idx: usize) -> TextPosition {
+    let line = rope.byte_to_line(byte_idx);
+    TextPosition {
+        line,
+        column: byte_idx - rope.line_to_byte(line),
+    }
+}
+
+/// Strip the trailing line break, if any, from a line.
+fn line_content(line: RopeSlice<'_>) -> RopeSlice<'_> {
+    let mut len = line.len_chars();
+    if len > 0 && line.char(len - 1) == '\n' {
+        len -= 1;
+    }
+    if len > 0 && line.char(len - 1) == '\r' {
+        len -= 1;
+    }
+    line.slice(..len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_offset_edits_are_undone_and_redone() {
+        // "é" takes two bytes, so byte and char offsets differ after it.
+        let mut buffer = Buffer::from("caf\u{e9} au lait");
+        buffer.insert_at_byte(5, " cr\u{e8}me");
+        assert_eq!(buffer.to_string(), "caf\u{e9} cr\u{e8}me au lait");
+        buffer.remove_bytes(13..16);
+        assert_eq!(buffer.to_string(), "caf\u{e9} cr\u{e8}me lait");
+
+        assert_eq!(buffer.undo(), Some(Selection { anchor: 11, head: 14 }));
+        assert_eq!(buffer.to_string(), "caf\u{e9} cr\u{e8}me au lait");
+        assert_eq!(buffer.undo(), Some(Selection::caret(4)));
+        assert_eq!(buffer.to_string(), "caf\u{e9} au lait");
+        assert!(!buffer.is_modified());
+
+        assert_eq!(buffer.redo(), Some(Selection::caret(10)));
+        assert_eq!(buffer.redo(), Some(Selection::caret(11)));
+        assert_eq!(buffer.to_string(), "caf\u{e9} cr\u{e8}me lait");
+    }
+}
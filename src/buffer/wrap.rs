@@ -0,0 +1,19 @@
+/// How lines longer than the view are shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    /// Keep each line on one row and scroll horizontally to see the rest.
+    None,
+    /// Wrap at the width of the window.
+    #[default]
+    Window,
+    /// Wrap after this many columns, whatever the width of the window.
+    Column(usize),
+}
+
+/// Settings for wrapping the lines of a buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WrapSettings {
+    pub wrap: Wrap,
+    /// Columns to indent the continuation rows of a wrapped line by.
+    pub continuation_indent: usize,
+}
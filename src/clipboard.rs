@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+/// Which of the system's selections to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClipboardKind {
+    /// The clipboard of explicit copy, cut and paste.
+    Clipboard,
+    /// The X11 primary selection, set by selecting text and pasted with the
+    /// middle button. Platforms without one ignore it.
+    Primary,
+}
+
+pub trait Clipboard {
+    /// The text in the clipboard, or `None` if it is empty or holds something
+    /// that is not text.
+    fn get_text(&mut self, kind: ClipboardKind) -> Option<String>;
+
+    fn set_text(&mut self, kind: ClipboardKind, text: &str) -> anyhow::Result<()>;
+}
+
+/// The clipboard of the desktop session, on X11, Wayland and other platforms.
+pub struct SystemClipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> anyhow::Result<SystemClipboard> {
+        let clipboard = arboard::Clipboard::new().context("failed to connect to the clipboard")?;
+        Ok(SystemClipboard { clipboard })
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))))]
+impl Clipboard for SystemClipboard {
+    fn get_text(&mut self, kind: ClipboardKind) -> Option<String> {
+        use arboard::GetExtLinux;
+
+        self.clipboard.get().clipboard(linux_kind(kind)).text().ok()
+    }
+
+    fn set_text(&mut self, kind: ClipboardKind, text: &str) -> anyhow::Result<()> {
+        use arboard::SetExtLinux;
+
+        self.clipboard
+            .set()
+            .clipboard(linux_kind(kind))
+            .text(text)
+            .context("failed to set the clipboard")
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))))]
+fn linux_kind(kind: ClipboardKind) -> arboard::LinuxClipboardKind {
+    match kind {
+        ClipboardKind::Clipboard => arboard::LinuxClipboardKind::Clipboard,
+        ClipboardKind::Primary => arboard::LinuxClipboardKind::Primary,
+    }
+}
+
+#[cfg(not(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten")))))]
+impl Clipboard for SystemClipboard {
+    fn get_text(&mut self, kind: ClipboardKind) -> Option<String> {
+        match kind {
+            ClipboardKind::Clipboard => self.clipboard.get_text().ok(),
+            ClipboardKind::Primary => None,
+        }
+    }
+
+    fn set_text(&mut self, kind: ClipboardKind, text: &str) -> anyhow::Result<()> {
+        match kind {
+            ClipboardKind::Clipboard => self.clipboard.set_text(text).context("failed to set the clipboard"),
+            ClipboardKind::Primary => Ok(()),
+        }
+    }
+}
+
+/// A clipboard that only lives in this process, for when there is no system
+/// clipboard and for exercising editing code without a display.
+#[derive(Default)]
+pub struct MemoryClipboard {
+    contents: HashMap<ClipboardKind, String>,
+}
+
+impl Clipboard for MemoryClipboard {
+    fn get_text(&mut self, kind: ClipboardKind) -> Option<String> {
+        self.contents.get(&kind).cloned()
+    }
+
+    fn set_text(&mut self, kind: ClipboardKind, text: &str) -> anyhow::Result<()> {
+        self.contents.insert(kind, text.to_owned());
+        Ok(())
+    }
+}
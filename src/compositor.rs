@@ -54,9 +54,15 @@ pub struct Compositor {
 }
 
 impl Compositor {
-    pub fn init(event_loop: &ActiveEventLoop, size: LogicalSize<f32>) -> anyhow::Result<(Compositor, RenderApi)> {
+    /// Open a window cleared to `clear_color` where nothing is painted.
+    pub fn init(
+        event_loop: &ActiveEventLoop,
+        size: LogicalSize<f32>,
+        title: &str,
+        clear_color: ColorF,
+    ) -> anyhow::Result<(Compositor, RenderApi)> {
         let window_attributes = Window::default_attributes()
-            .with_title("Wedit")
+            .with_title(title)
             .with_inner_size(size);
 
         let display_builder = glutin_winit::DisplayBuilder::new()
@@ -107,7 +113,7 @@ impl Compositor {
         let webrender_options = WebRenderOptions {
             enable_aa: true,
             enable_subpixel_aa: true,
-            clear_color: ColorF::new(1.0, 1.0, 1.0, 0.0),
+            clear_color,
             ..Default::default()
         };
 
@@ -138,6 +144,19 @@ impl Compositor {
         self.window.scale_factor()
     }
 
+    pub fn set_title(&self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    /// Color to clear the window to on the next frames.
+    pub fn set_clear_color(&mut self, color: ColorF) {
+        self.renderer.set_clear_color(color);
+    }
+
+    pub fn request_redraw(&self) {
+        self.window.request_redraw();
+    }
+
     pub fn update(&mut self) {
         self.renderer.update();
         self.renderer.render(self.device_size(), 0).unwrap();
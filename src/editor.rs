@@ -0,0 +1,570 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::buffer::Buffer;
+use crate::buffer::history::EditKind;
+use crate::clipboard::{Clipboard, ClipboardKind};
+use crate::layout::caret::{Caret, CaretStyle};
+use crate::syntax::SyntaxTree;
+
+/// Number of columns between tab stops.
+const TAB_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Movement {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    PageUp,
+    PageDown,
+}
+
+impl Movement {
+    fn is_vertical(self) -> bool {
+        matches!(self, Movement::Up | Movement::Down | Movement::PageUp | Movement::PageDown)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Insert(String),
+    Backspace,
+    Delete,
+    Newline,
+    Tab,
+    Move(Movement),
+    /// Move the head of the selection, keeping its anchor in place.
+    Select(Movement),
+    Undo,
+    Redo,
+}
+
+/// A range of the buffer between the `anchor`, where selecting started, and
+/// the `head`, where the caret is. The two are equal when nothing is selected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub fn caret(position: usize) -> Selection {
+        Selection {
+            anchor: position,
+            head: position,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+}
+
+/// Applies editing commands to a buffer at the selection.
+pub struct Editor {
+    buffer: Rc<RefCell<Buffer>>,
+    selection: Selection,
+    /// The column vertical movement tries to return to, so that moving
+    /// through a short line does not lose the original column.
+    goal_column: Option<usize>,
+    /// Number of lines moved by PageUp/PageDown.
+    page_lines: usize,
+    /// The selections each syntax-aware expansion went from and to, the
+    /// latest last, for shrinking back through them.
+    expansions: Vec<(Selection, Selection)>,
+    pub caret_style: CaretStyle,
+}
+
+impl Editor {
+    pub fn new(buffer: Rc<RefCell<Buffer>>) -> Editor {
+        Editor {
+            buffer,
+            selection: Selection::default(),
+            goal_column: None,
+            page_lines: 1,
+            expansions: Vec::new(),
+            caret_style: CaretStyle::default(),
+        }
+    }
+
+    pub fn buffer(&self) -> &Rc<RefCell<Buffer>> {
+        &self.buffer
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.selection.head
+    }
+
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    pub fn caret(&self) -> Caret {
+        Caret {
+            position: self.selection.head,
+            style: self.caret_style,
+        }
+    }
+
+    /// Move the insertion point, e.g. in response to a click.
+    pub fn set_cursor(&mut self, position: usize) {
+        self.set_selection(Selection::caret(self.clamp(position)));
+    }
+
+    /// Extend the selection to `position`, e.g. while dragging.
+    pub fn select_to(&mut self, position: usize) {
+        let head = self.clamp(position);
+        self.set_selection(Selection { anchor: self.selection.anchor, head });
+    }
+
+    /// Change the selection without editing. This ends the current run of
+    /// typing, so the next edit starts a new undo step.
+    fn set_selection(&mut self, selection: Selection) {
+        if selection != self.selection {
+            self.buffer.borrow_mut().history_mut().seal();
+        }
+        self.selection = selection;
+        self.goal_column = None;
+    }
+
+    /// Select the word, or run of whitespace or punctuation, around `position`.
+    pub fn select_word(&mut self, position: usize) {
+        let buffer = self.buffer.borrow();
+        let position = position.min(buffer.len_chars());
+        let line = buffer.char_to_line(position);
+        let line_start = buffer.line_to_char(line);
+        let line_end = line_start + buffer.line_len_chars(line);
+
+        // Prefer the char after the position, unless it is at the end of the line.
+        let class = if position < line_end {
+            CharClass::of(buffer.char(position))
+        } else if position > line_start {
+            CharClass::of(buffer.char(position - 1))
+        } else {
+            drop(buffer);
+            self.set_cursor(position);
+            return;
+        };
+
+        let mut start = position;
+        while start > line_start && CharClass::of(buffer.char(start - 1)) == class {
+            start -= 1;
+        }
+        let mut end = position;
+        while end < line_end && CharClass::of(buffer.char(end)) == class {
+            end += 1;
+        }
+        drop(buffer);
+        self.set_selection(Selection { anchor: start, head: end });
+    }
+
+    /// Select the whole line around `position`, including its line break.
+    pub fn select_line(&mut self, position: usize) {
+        let buffer = self.buffer.borrow();
+        let line = buffer.char_to_line(position.min(buffer.len_chars()));
+        let start = buffer.line_to_char(line);
+        let end = if line + 1 < buffer.len_lines() {
+            buffer.line_to_char(line + 1)
+        } else {
+            buffer.len_chars()
+        };
+        drop(buffer);
+        self.set_selection(Selection { anchor: start, head: end });
+    }
+
+    pub fn selected_text(&self) -> String {
+        self.buffer.borrow().slice(self.selection.range()).to_string()
+    }
+
+    /// Copy the selection, returning whether anything was selected.
+    pub fn copy(&self, clipboard: &mut dyn Clipboard, kind: ClipboardKind) -> anyhow::Result<bool> {
+        if self.selection.is_empty() {
+            return Ok(false);
+        }
+        clipboard.set_text(kind, &self.selected_text())?;
+        Ok(true)
+    }
+
+    /// Move the selection to the clipboard, as an undo step of its own.
+    pub fn cut(&mut self, clipboard: &mut dyn Clipboard) -> anyhow::Result<bool> {
+        if !self.copy(clipboard, ClipboardKind::Clipboard)? {
+            return Ok(false);
+        }
+        self.replace(self.selection.range(), "", EditKind::Cut);
+        Ok(true)
+    }
+
+    /// Replace the selection with the text in the clipboard, as an undo step of
+    /// its own however many lines it has.
+    pub fn paste(&mut self, clipboard: &mut dyn Clipboard, kind: ClipboardKind) -> bool {
+        let Some(text) = clipboard.get_text(kind) else {
+            return false;
+        };
+        if text.is_empty() {
+            return false;
+        }
+        self.replace(self.selection.range(), &text, EditKind::Paste);
+        true
+    }
+
+    /// Grow the selection to the smallest syntax node around it, returning
+    /// whether it changed.
+    pub fn expand_selection(&mut self, tree: &SyntaxTree) -> bool {
+        let snapshot = self.buffer.borrow().snapshot();
+        let Some(range) = tree.expand(&snapshot, self.selection.range()) else {
+            return false;
+        };
+        if self.expansions.last().is_some_and(|&(_, to)| to != self.selection) {
+            self.expansions.clear();
+        }
+        let from = self.selection;
+        self.set_selection(Selection { anchor: range.start, head: range.end });
+        self.expansions.push((from, self.selection));
+        true
+    }
+
+    /// Undo the last expansion of the selection, if the selection has not
+    /// changed since. Returns whether it did.
+    pub fn shrink_selection(&mut self) -> bool {
+        match self.expansions.pop() {
+            Some((from, to)) if to == self.selection => {
+                self.set_selection(from);
+                true
+            }
+            _ => {
+                self.expansions.clear();
+                false
+            }
+        }
+    }
+
+    pub fn set_page_lines(&mut self, lines: usize) {
+        self.page_lines = lines.max(1);
+    }
+
+    /// Run a command, returning whether the buffer or the selection changed.
+    pub fn execute(&mut self, command: Command) -> bool {
+        let old_selection = self.selection;
+        if !matches!(command, Command::Move(movement) | Command::Select(movement) if movement.is_vertical()) {
+            self.goal_column = None;
+        }
+
+        match command {
+            Command::Insert(text) => {
+                if text.is_empty() {
+                    return false;
+                }
+                self.insert(&text);
+                true
+            }
+            Command::Newline => {
+                self.insert("\n");
+                true
+            }
+            Command::Tab => {
+                let column = self.column(self.selection.start());
+                let spaces = TAB_SIZE - column % TAB_SIZE;
+                self.insert(&" ".repeat(spaces));
+                true
+            }
+            Command::Backspace => {
+                let range = if self.selection.is_empty() {
+                    self.prev_position(self.selection.head)..self.selection.head
+                } else {
+                    self.selection.range()
+                };
+                if range.is_empty() {
+                    return false;
+                }
+                self.replace(range, "", EditKind::Deleting);
+                true
+            }
+            Command::Delete => {
+                let range = if self.selection.is_empty() {
+                    self.selection.head..self.next_position(self.selection.head)
+                } else {
+                    self.selection.range()
+                };
+                if range.is_empty() {
+                    return false;
+                }
+                self.replace(range, "", EditKind::Deleting);
+                true
+            }
+            Command::Move(movement) => {
+                let position = match movement {
+                    // Moving sideways out of a selection goes to its edge.
+                    Movement::Left if !self.selection.is_empty() => self.selection.start(),
+                    Movement::Right if !self.selection.is_empty() => self.selection.end(),
+                    _ => self.move_position(self.selection.head, movement),
+                };
+                self.update_selection(Selection::caret(position));
+                self.selection != old_selection
+            }
+            Command::Select(movement) => {
+                let head = self.move_position(self.selection.head, movement);
+                self.update_selection(Selection { anchor: self.selection.anchor, head });
+                self.selection != old_selection
+            }
+            Command::Undo => {
+                let Some(selection) = self.buffer.borrow_mut().undo() else {
+                    return false;
+                };
+                self.selection = selection;
+                true
+            }
+            Command::Redo => {
+                let Some(selection) = self.buffer.borrow_mut().redo() else {
+                    return false;
+                };
+                self.selection = selection;
+                true
+            }
+        }
+    }
+
+    /// Like `set_selection`, but keeps the goal column for vertical movement.
+    fn update_selection(&mut self, selection: Selection) {
+        let goal_column = self.goal_column;
+        self.set_selection(selection);
+        self.goal_column = goal_column;
+    }
+
+    /// Replace the selection with `text` as a typing edit, leaving the caret after it.
+    fn insert(&mut self, text: &str) {
+        self.replace(self.selection.range(), text, EditKind::Typing);
+    }
+
+    /// Replace `range` with `text`, leaving the caret after it.
+    fn replace(&mut self, range: Range<usize>, text: &str, kind: EditKind) {
+        let selection = Selection::caret(range.start + text.chars().count());
+        self.buffer.borrow_mut().edit(range, text, kind, self.selection, selection);
+        self.selection = selection;
+    }
+
+    fn clamp(&self, position: usize) -> usize {
+        position.min(self.buffer.borrow().len_chars())
+    }
+
+    fn move_position(&mut self, position: usize, movement: Movement) -> usize {
+        match movement {
+            Movement::Left => self.prev_position(position),
+            Movement::Right => self.next_position(position),
+            Movement::Up => self.move_lines(position, -1),
+            Movement::Down => self.move_lines(position, 1),
+            Movement::PageUp => self.move_lines(position, -(self.page_lines as isize)),
+            Movement::PageDown => self.move_lines(position, self.page_lines as isize),
+            Movement::LineStart => {
+                let buffer = self.buffer.borrow();
+                buffer.line_to_char(buffer.char_to_line(position))
+            }
+            Movement::LineEnd => {
+                let buffer = self.buffer.borrow();
+                let line = buffer.char_to_line(position);
+                buffer.line_to_char(line) + buffer.line_len_chars(line)
+            }
+        }
+    }
+
+    /// Column of a position, counted in chars from the start of its line.
+    fn column(&self, position: usize) -> usize {
+        let buffer = self.buffer.borrow();
+        position - buffer.line_to_char(buffer.char_to_line(position))
+    }
+
+    fn move_lines(&mut self, position: usize, delta: isize) -> usize {
+        let goal_column = match self.goal_column {
+            Some(column) => column,
+            None => {
+                let column = self.column(position);
+                self.goal_column = Some(column);
+                column
+            }
+        };
+        let buffer = self.buffer.borrow();
+        let line = buffer.char_to_line(position);
+        let last_line = buffer.len_lines() - 1;
+        let target = line.saturating_add_signed(delta).min(last_line);
+        if target == line {
+            // Moving past the first or last line goes to its start or end.
+            return if delta < 0 { 0 } else if line == last_line { buffer.len_chars() } else { position };
+        }
+        buffer.line_to_char(target) + goal_column.min(buffer.line_len_chars(target))
+    }
+
+    /// The position one grapheme cluster before `position`, so that e.g. CRLF
+    /// and an emoji sequence are stepped over as one.
+    fn prev_position(&self, position: usize) -> usize {
+        self.buffer.borrow().prev_grapheme_boundary(position)
+    }
+
+    /// The position one grapheme cluster after `position`.
+    fn next_position(&self, position: usize) -> usize {
+        self.buffer.borrow().next_grapheme_boundary(position)
+    }
+}
+
+/// Kinds of chars that double-click selects runs of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Word,
+    Whitespace,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(c: char) -> CharClass {
+        if c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else if c.is_whitespace() {
+            CharClass::Whitespace
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clipboard::MemoryClipboard;
+
+    use super::*;
+
+    fn editor(text: &str) -> Editor {
+        Editor::new(Rc::new(RefCell::new(Buffer::from(text))))
+    }
+
+    /// The positions moving by `movement` from `start` stops at, until it
+    /// stops moving.
+    fn steps(editor: &mut Editor, start: usize, movement: Movement) -> Vec<usize> {
+        editor.set_cursor(start);
+        let mut positions = Vec::new();
+        while editor.execute(Command::Move(movement)) {
+            positions.push(editor.cursor());
+        }
+        positions
+    }
+
+    #[test]
+    fn caret_moves_by_grapheme_cluster() {
+        // A combining accent, CRLF and a family emoji joined from three.
+        let text = "e\u{301}x\r\n\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}!";
+        let mut editor = editor(text);
+        assert_eq!(steps(&mut editor, 0, Movement::Right), [2, 3, 5, 10, 11]);
+        assert_eq!(steps(&mut editor, 11, Movement::Left), [10, 5, 3, 2, 0]);
+
+        editor.execute(Command::Move(Movement::Right));
+        editor.execute(Command::Backspace);
+        assert_eq!(editor.buffer().borrow().to_string(), "x\r\n\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}!");
+        editor.execute(Command::Move(Movement::Right));
+        editor.execute(Command::Delete);
+        assert_eq!(editor.buffer().borrow().to_string(), "x\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}!");
+    }
+
+    #[test]
+    fn graphemes_split_across_rope_chunks_are_kept_whole() {
+        let mut editor = editor(&"e\u{301}".repeat(2000));
+        let right = steps(&mut editor, 0, Movement::Right);
+        assert_eq!(right, (1..=2000).map(|i| i * 2).collect::<Vec<_>>());
+        let left = steps(&mut editor, 4000, Movement::Left);
+        assert_eq!(left, (0..2000).rev().map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.buffer().borrow().to_string()
+    }
+
+    #[test]
+    fn multi_line_selections_are_copied_cut_and_pasted() {
+        let mut clipboard = MemoryClipboard::default();
+        let mut editor = editor("one\ntwo\nthree\n");
+        // From the "ne" of the first line to the "th" of the third.
+        editor.set_cursor(1);
+        editor.select_to(10);
+        assert!(editor.copy(&mut clipboard, ClipboardKind::Clipboard).unwrap());
+        assert_eq!(clipboard.get_text(ClipboardKind::Clipboard).as_deref(), Some("ne\ntwo\nth"));
+        assert_eq!(text(&editor), "one\ntwo\nthree\n");
+
+        assert!(editor.cut(&mut clipboard).unwrap());
+        assert_eq!(text(&editor), "oree\n");
+        assert_eq!(editor.selection(), Selection::caret(1));
+
+        editor.set_cursor(5);
+        assert!(editor.paste(&mut clipboard, ClipboardKind::Clipboard));
+        assert_eq!(text(&editor), "oree\nne\ntwo\nth");
+        assert_eq!(editor.selection(), Selection::caret(14));
+
+        // The paste, then the cut, are undone a step each.
+        assert!(editor.execute(Command::Undo));
+        assert_eq!(text(&editor), "oree\n");
+        assert!(editor.execute(Command::Undo));
+        assert_eq!(text(&editor), "one\ntwo\nthree\n");
+        assert_eq!(editor.selection(), Selection { anchor: 1, head: 10 });
+    }
+
+    #[test]
+    fn pasting_replaces_the_selection() {
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.set_text(ClipboardKind::Primary, "a\nb").unwrap();
+        let mut editor = editor("xyz");
+        editor.set_cursor(1);
+        editor.select_to(2);
+        assert!(editor.paste(&mut clipboard, ClipboardKind::Primary));
+        assert_eq!(text(&editor), "xa\nbz");
+        assert!(!editor.paste(&mut clipboard, ClipboardKind::Clipboard));
+
+        assert!(editor.execute(Command::Undo));
+        assert_eq!(text(&editor), "xyz");
+        assert_eq!(editor.selection(), Selection { anchor: 1, head: 2 });
+    }
+
+    #[test]
+    fn double_click_selects_runs_of_one_kind_of_char() {
+        let mut editor = editor("let foo_bar = a+b;\n\n  last");
+        let mut word_at = |position| {
+            editor.select_word(position);
+            (editor.selected_text(), editor.selection())
+        };
+        assert_eq!(word_at(5), ("foo_bar".to_owned(), Selection { anchor: 4, head: 11 }));
+        // At the boundary between two runs, the run after the position wins.
+        assert_eq!(word_at(11).0, " ");
+        assert_eq!(word_at(15).0, "+");
+        assert_eq!(word_at(14).0, "a");
+        // At the end of a line, the run before it is selected instead.
+        assert_eq!(word_at(18), (";".to_owned(), Selection { anchor: 17, head: 18 }));
+        // An empty line has nothing to select.
+        assert_eq!(word_at(19), (String::new(), Selection::caret(19)));
+        assert_eq!(word_at(20).0, "  ");
+        assert_eq!(word_at(100), ("last".to_owned(), Selection { anchor: 22, head: 26 }));
+    }
+
+    #[test]
+    fn triple_click_selects_the_line_with_its_line_break() {
+        let mut editor = editor("one\r\ntwo\nlast");
+        editor.select_line(1);
+        assert_eq!(editor.selected_text(), "one\r\n");
+        editor.select_line(5);
+        assert_eq!(editor.selection(), Selection { anchor: 5, head: 9 });
+        // The last line has no line break to select.
+        editor.select_line(10);
+        assert_eq!(editor.selection(), Selection { anchor: 9, head: 13 });
+        editor.select_line(13);
+        assert_eq!(editor.selected_text(), "last");
+    }
+}
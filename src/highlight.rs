@@ -0,0 +1,312 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Context;
+use syntect::highlighting::{
+    FontStyle as SyntectFontStyle, HighlightIterator, HighlightState, Highlighter, Style, Theme as SyntaxTheme,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet, SyntaxSetBuilder};
+use ttf_parser::{Style as FontStyle, Weight};
+
+use crate::buffer::edits::Edit;
+use crate::buffer::{Buffer, Snapshot};
+use crate::layout::widget::{Span, SpanStyle};
+use crate::resources::resource_dir;
+use crate::theme::{to_color, Theme};
+
+/// Grammars bundled with the editor, in its resource directory.
+const BUNDLED_SYNTAXES: &str = "syntaxes";
+
+/// Load the Sublime Text grammars (`.sublime-syntax` files) bundled with the
+/// editor and those in `$XDG_CONFIG_HOME/wedit/syntaxes`. Later grammars win
+/// when several claim the same file extension.
+pub fn load_syntaxes() -> anyhow::Result<SyntaxSet> {
+    let mut builder = SyntaxSetBuilder::new();
+    builder.add_plain_text_syntax();
+    for dir in syntax_dirs() {
+        if dir.is_dir() {
+            builder
+                .add_from_folder(&dir, true)
+                .with_context(|| format!("failed to load grammars from {}", dir.display()))?;
+        }
+    }
+    Ok(builder.build())
+}
+
+/// Only the plain text grammar, for when the others fail to load.
+pub fn plain_text_syntaxes() -> SyntaxSet {
+    let mut builder = SyntaxSetBuilder::new();
+    builder.add_plain_text_syntax();
+    builder.build()
+}
+
+fn syntax_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![resource_dir().join(BUNDLED_SYNTAXES)];
+    dirs.extend(config_dir().map(|config| config.join("syntaxes")));
+    dirs
+}
+
+/// The editor's directory in `$XDG_CONFIG_HOME`, or `~/.config` without it.
+pub fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("wedit"))
+}
+
+/// Styles the lines of a buffer, keeping whatever it works out from the text
+/// up to date with the edits made to it.
+pub trait Highlight {
+    /// Catch up with the edits made to `buffer` since the last update.
+    fn update(&mut self, buffer: &Buffer);
+
+    /// The styled spans of a line of `snapshot`, which must be the contents of
+    /// the buffer as of the last update. They are sorted and do not overlap.
+    fn line_spans(&mut self, snapshot: &Snapshot, line_idx: usize) -> Vec<Span>;
+
+    /// Style the text with `theme` from now on.
+    fn set_theme(&mut self, theme: &Theme);
+}
+
+/// Tokenizer state at the start of a line.
+type LineState = (ParseState, HighlightState);
+
+#[derive(Default)]
+struct Line {
+    /// State at the start of the line, once the line before it is tokenized.
+    state: Option<LineState>,
+    /// Styled spans with chars counted from the start of the line, or `None`
+    /// if the line has to be tokenized again.
+    spans: Option<Vec<Span>>,
+}
+
+/// Styles the text of a buffer with a grammar, one line at a time.
+///
+/// The state of the tokenizer at the start of every line is kept, so that an
+/// edit only tokenizes the lines it touched again, and the lines after them
+/// until their starting state turns out not to have changed.
+pub struct SyntaxHighlighter {
+    syntaxes: Rc<SyntaxSet>,
+    syntax: SyntaxReference,
+    theme: SyntaxTheme,
+    /// Revision of the buffer `lines` is up to date with.
+    revision: u64,
+    /// The lines from the top of the buffer down to the last one tokenized.
+    lines: Vec<Line>,
+    /// Every line above this one has its spans.
+    first_stale: usize,
+}
+
+impl SyntaxHighlighter {
+    /// A highlighter for `buffer`, with the grammar for its file extension or
+    /// first line, or plain text if no grammar matches.
+    pub fn new(syntaxes: Rc<SyntaxSet>, buffer: &Buffer, theme: &Theme) -> SyntaxHighlighter {
+        let by_extension = buffer
+            .path()
+            .and_then(|path| path.extension().or(path.file_name()))
+            .and_then(|name| syntaxes.find_syntax_by_extension(&name.to_string_lossy()));
+        let syntax = by_extension
+            .or_else(|| syntaxes.find_syntax_by_first_line(&buffer.line(0).to_string()))
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+            .clone();
+        SyntaxHighlighter {
+            syntaxes,
+            syntax,
+            theme: theme.syntax.clone(),
+            revision: buffer.revision(),
+            lines: Vec::new(),
+            first_stale: 0,
+        }
+    }
+
+    fn edit(&mut self, edit: &Edit) {
+        let start = edit.start.line;
+        if start >= self.lines.len() {
+            return;
+        }
+        // The state at the start of the edited line is unaffected, but the
+        // lines it was split into or joined with have to be tokenized from it.
+        self.lines[start].spans = None;
+        let removed = start + 1..(edit.old_end.line + 1).min(self.lines.len());
+        let inserted = edit.new_end.line - start;
+        self.lines.splice(removed, (0..inserted).map(|_| Line::default()));
+        self.first_stale = self.first_stale.min(start);
+    }
+
+    /// Tokenize the stale lines down to `last`.
+    fn tokenize(&mut self, snapshot: &Snapshot, last: usize) {
+        let highlighter = Highlighter::new(&self.theme);
+        let default_style = highlighter.get_default();
+        if self.lines.len() <= last {
+            self.lines.resize_with(last + 1, Line::default);
+        }
+
+        let mut line_idx = self.first_stale;
+        while line_idx <= last {
+            if self.lines[line_idx].spans.is_some() {
+                line_idx += 1;
+                continue;
+            }
+            let (mut parse_state, mut highlight_state) = self.lines[line_idx].state.clone().unwrap_or_else(|| {
+                (ParseState::new(&self.syntax), HighlightState::new(&highlighter, ScopeStack::new()))
+            });
+            let mut text = snapshot.line_text(line_idx);
+            let len = text.chars().count();
+            // Grammars are loaded to match line breaks at the end of lines.
+            text.push('\n');
+            let ops = parse_state.parse_line(&text, &self.syntaxes).unwrap_or_default();
+
+            let mut spans: Vec<Span> = Vec::new();
+            let mut offset = 0;
+            for (style, token) in HighlightIterator::new(&mut highlight_state, &ops, &text, &highlighter) {
+                let start = offset;
+                offset = (offset + token.chars().count()).min(len);
+                if style == default_style || start >= offset {
+                    continue;
+                }
+                let style = span_style(style);
+                match spans.last_mut() {
+                    Some(span) if span.range.end == start && span.style == style => span.range.end = offset,
+                    _ => spans.push(Span { range: start..offset, style }),
+                }
+            }
+            self.lines[line_idx].spans = Some(spans);
+
+            // The next line keeps its spans if it starts in the same state as
+            // before, and so do the lines after it.
+            let state = (parse_state, highlight_state);
+            match self.lines.get_mut(line_idx + 1) {
+                Some(next) if next.state.as_ref() == Some(&state) => {}
+                Some(next) => {
+                    next.state = Some(state);
+                    next.spans = None;
+                }
+                None if line_idx + 1 < snapshot.len_lines() => self.lines.push(Line {
+                    state: Some(state),
+                    spans: None,
+                }),
+                None => {}
+            }
+            line_idx += 1;
+        }
+        self.first_stale = line_idx;
+    }
+}
+
+impl Highlight for SyntaxHighlighter {
+    fn update(&mut self, buffer: &Buffer) {
+        if self.revision == buffer.revision() {
+            return;
+        }
+        match buffer.edits_since(self.revision) {
+            Some(edits) => {
+                for edit in edits {
+                    self.edit(edit);
+                }
+            }
+            None => {
+                self.lines.clear();
+                self.first_stale = 0;
+            }
+        }
+        self.revision = buffer.revision();
+    }
+
+    /// Lines above the one asked for that were edited or never seen are
+    /// tokenized first.
+    fn line_spans(&mut self, snapshot: &Snapshot, line_idx: usize) -> Vec<Span> {
+        if self.first_stale <= line_idx {
+            self.tokenize(snapshot, line_idx);
+        }
+        let line_start = snapshot.line_to_char(line_idx);
+        self.lines[line_idx]
+            .spans
+            .iter()
+            .flatten()
+            .map(|span| Span {
+                range: line_start + span.range.start..line_start + span.range.end,
+                style: span.style.clone(),
+            })
+            .collect()
+    }
+
+    /// The saved tokenizer states hold styles of the old theme, so every line
+    /// is tokenized again.
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.syntax.clone();
+        self.lines.clear();
+        self.first_stale = 0;
+    }
+}
+
+/// The span style for text in a theme's `style`.
+pub fn span_style(style: Style) -> SpanStyle {
+    SpanStyle {
+        color: Some(to_color(style.foreground)),
+        font_weight: style.font_style.contains(SyntectFontStyle::BOLD).then_some(Weight::Bold),
+        font_style: style.font_style.contains(SyntectFontStyle::ITALIC).then_some(FontStyle::Italic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::buffer::history::EditKind;
+    use crate::editor::Selection;
+
+    use super::*;
+
+    /// A highlighter for `buffer` with the bundled TOML grammar.
+    fn toml_highlighter(buffer: &Buffer) -> SyntaxHighlighter {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_from_folder(resource_dir().join(BUNDLED_SYNTAXES), true).unwrap();
+        let syntaxes = Rc::new(builder.build());
+        let syntax = syntaxes.find_syntax_by_extension("toml").unwrap().clone();
+        SyntaxHighlighter {
+            syntaxes,
+            syntax,
+            theme: Theme::dark().syntax,
+            revision: buffer.revision(),
+            lines: Vec::new(),
+            first_stale: 0,
+        }
+    }
+
+    fn edit(buffer: &mut Buffer, range: Range<usize>, text: &str) {
+        let selection = Selection::caret(range.start);
+        buffer.edit(range, text, EditKind::Other, selection, selection);
+    }
+
+    /// Catch up with `buffer` and tokenize down to `line_idx`, returning the
+    /// lines below it that are left to tokenize again.
+    fn stale_lines_below(highlighter: &mut SyntaxHighlighter, buffer: &Buffer, line_idx: usize) -> Vec<usize> {
+        highlighter.update(buffer);
+        highlighter.line_spans(&buffer.snapshot(), line_idx);
+        (line_idx + 1..highlighter.lines.len()).filter(|&idx| highlighter.lines[idx].spans.is_none()).collect()
+    }
+
+    #[test]
+    fn edits_tokenize_later_lines_only_if_their_starting_state_changed() {
+        let mut buffer = Buffer::from("a = 1\nb = \"x\"\nc = 2\nd = 3\n");
+        let mut highlighter = toml_highlighter(&buffer);
+        assert!(stale_lines_below(&mut highlighter, &buffer, 4).is_empty());
+
+        // Editing the string within its line leaves the lines below alone.
+        edit(&mut buffer, 12..12, "yz");
+        assert!(stale_lines_below(&mut highlighter, &buffer, 1).is_empty());
+
+        // Opening a multi-line string changes the state the lines below start
+        // in, so they are tokenized again, one at a time, as part of it.
+        edit(&mut buffer, 10..11, "\"\"\"");
+        assert_eq!(stale_lines_below(&mut highlighter, &buffer, 1), [2]);
+        assert_eq!(stale_lines_below(&mut highlighter, &buffer, 2), [3]);
+        let snapshot = buffer.snapshot();
+        let string = highlighter.line_spans(&snapshot, 1).pop().unwrap().style;
+        let line_start = snapshot.line_to_char(2);
+        assert_eq!(
+            highlighter.line_spans(&snapshot, 2),
+            [Span { range: line_start..line_start + 5, style: string }]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use app_units::Au;
+
+use super::fragment::{BoxFragment, BoxKind, Fragment, TextFragment};
+use super::{Point, Rect, Size};
+
+const BAR_WIDTH: f32 = 2.0;
+const UNDERLINE_HEIGHT: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaretStyle {
+    #[default]
+    Bar,
+    Block,
+    Underline,
+}
+
+/// The insertion point, as a char index into the buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct Caret {
+    pub position: usize,
+    pub style: CaretStyle,
+}
+
+impl BoxFragment {
+    /// The rect to paint for `caret`, relative to this fragment's containing block.
+    pub fn caret_rect(&self, caret: &Caret) -> Option<Rect<Au>> {
+        // A position at the end of a wrapped line also starts the next one; prefer
+        // the fragment that contains the position over the one it ends.
+        let mut fallback = None;
+        for fragment in &self.children {
+            let rect = match fragment {
+                Fragment::Text(text_fragment) => {
+                    if !text_fragment.range.contains(&caret.position) {
+                        if text_fragment.range.end == caret.position && fallback.is_none() {
+                            fallback = text_fragment.caret_rect(caret);
+                        }
+                        continue;
+                    }
+                    text_fragment.caret_rect(caret)
+                }
+                Fragment::Box(box_fragment) if box_fragment.kind == BoxKind::Gutter => continue,
+                Fragment::Box(box_fragment) => box_fragment.caret_rect(caret),
+            };
+            if rect.is_some() {
+                return rect.map(|rect| rect.translate(self.rect.origin));
+            }
+        }
+        fallback.map(|rect| rect.translate(self.rect.origin))
+    }
+}
+
+impl TextFragment {
+    /// Distance from the left of the fragment to the logical start of the
+    /// glyph at `position`, which is its right edge in right-to-left text.
+    pub fn inline_offset(&self, position: usize) -> Au {
+        let before = self.glyphs
+            .iter()
+            .filter(|glyph| glyph.cluster < position)
+            .fold(Au(0), |offset, glyph| offset + glyph.advance);
+        if self.rtl { self.rect.size.width - before } else { before }
+    }
+
+    fn caret_rect(&self, caret: &Caret) -> Option<Rect<Au>> {
+        if caret.position < self.range.start || caret.position > self.range.end {
+            return None;
+        }
+        let mut i = self.rect.origin.i + self.inline_offset(caret.position);
+        // Block and underline carets cover the glyph after the caret, or half an
+        // em past the end of the line.
+        let glyph_width = self.glyphs
+            .iter()
+            .find(|glyph| glyph.cluster == caret.position)
+            .map(|glyph| glyph.advance)
+            .unwrap_or_else(|| Au::from_f32_px(self.font_size / 2.0));
+        // The glyph after the caret is to its left in right-to-left text.
+        if self.rtl && caret.style != CaretStyle::Bar {
+            i -= glyph_width;
+        }
+
+        let rect = match caret.style {
+            CaretStyle::Bar => Rect {
+                origin: Point::new(i, self.rect.origin.b),
+                size: Size { width: Au::from_f32_px(BAR_WIDTH), height: self.rect.size.height },
+            },
+            CaretStyle::Block => Rect {
+                origin: Point::new(i, self.rect.origin.b),
+                size: Size { width: glyph_width, height: self.rect.size.height },
+            },
+            CaretStyle::Underline => {
+                let height = Au::from_f32_px(UNDERLINE_HEIGHT);
+                Rect {
+                    origin: Point::new(i, self.rect.origin.b + self.rect.size.height - height),
+                    size: Size { width: glyph_width, height },
+                }
+            }
+        };
+        Some(rect)
+    }
+}
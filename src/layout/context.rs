@@ -1,50 +1,107 @@
 use std::collections::HashMap;
 
 use app_units::Au;
+use ttf_parser::{Style as FontStyle, Weight};
 use webrender::Transaction;
-use webrender_api::{FontKey, FontInstanceKey, DocumentId, units::DeviceIntSize};
+use webrender_api::{
+    DocumentId, FontInstanceKey, FontInstanceOptions, FontInstancePlatformOptions, FontKey, FontRenderMode,
+    units::DeviceIntSize
+};
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+use webrender_api::FontHinting;
 
-use super::{inline::FaceInfo, Size};
+use super::{font::{FontDatabase, DEFAULT_FONT_DATA}, inline::FaceInfo, Size};
 
 
+/// Display lists a font instance may go unused in before it is deleted.
+const FONT_INSTANCE_LIFETIME: u64 = 8;
+
+/// A font size in device pixels, in the 26.6 fixed point FreeType uses, so
+/// that fractional sizes can key a cache without collapsing together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FontSize(u32);
+
+impl FontSize {
+    pub fn from_px(px: f32) -> FontSize {
+        FontSize((px.max(0.0) * 64.0).round() as u32)
+    }
+
+    pub fn to_px(self) -> f32 {
+        self.0 as f32 / 64.0
+    }
+}
+
+/// How the glyphs of a font instance are rasterized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FontRenderOptions {
+    /// Antialias with subpixel (LCD) rendering rather than grayscale.
+    pub subpixel_aa: bool,
+    /// Fit outlines to the pixel grid.
+    pub hinting: bool,
+}
+
+impl Default for FontRenderOptions {
+    fn default() -> Self {
+        Self {
+            subpixel_aa: true,
+            hinting: true,
+        }
+    }
+}
+
+impl FontRenderOptions {
+    fn to_webrender(self) -> (FontInstanceOptions, Option<FontInstancePlatformOptions>) {
+        let options = FontInstanceOptions {
+            render_mode: if self.subpixel_aa { FontRenderMode::Subpixel } else { FontRenderMode::Alpha },
+            ..FontInstanceOptions::default()
+        };
+        // Only FreeType takes hinting options.
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        let platform_options = Some(FontInstancePlatformOptions {
+            hinting: match (self.hinting, self.subpixel_aa) {
+                (false, _) => FontHinting::None,
+                (true, true) => FontHinting::LCD,
+                (true, false) => FontHinting::Normal,
+            },
+            ..FontInstancePlatformOptions::default()
+        });
+        #[cfg(any(target_os = "macos", target_os = "windows"))]
+        let platform_options = None;
+        (options, platform_options)
+    }
+}
+
+struct FontInstance {
+    key: FontInstanceKey,
+    /// The display list this instance was last used in.
+    last_used: u64,
+}
+
 pub struct FontCache {
     font_keys: HashMap<FaceInfo, FontKey>,
-    font_instance_keys: HashMap<(FaceInfo, u8), FontInstanceKey>,
+    font_instances: HashMap<(FaceInfo, FontSize, FontRenderOptions), FontInstance>,
+    /// Counts the display lists built, for evicting unused font instances.
+    generation: u64,
     fonts: HashMap<FaceInfo, Vec<u8>>,
+    /// Whether each face has a glyph for the chars looked up so far.
+    coverage: HashMap<FaceInfo, HashMap<char, bool>>,
 }
 
 impl FontCache {
     pub fn new() -> FontCache {
         FontCache {
             font_keys: HashMap::new(),
-            font_instance_keys: HashMap::new(),
-            fonts: HashMap::new()
+            font_instances: HashMap::new(),
+            generation: 0,
+            fonts: HashMap::new(),
+            coverage: HashMap::new(),
         }
     }
-
-    // fn add_font(&mut self, face_info: &FaceInfo) {
-    //     let font_data = std::fs::read(&face_info.path).unwrap();
-
-    //     let mut txn = Transaction::new();
-    //     let font_key = self.api.generate_font_key();
-
-    //     txn.add_raw_font(font_key, font_data.clone(), face_info.index);
-    //     self.api.send_transaction(self.document_id, txn);
-    //     self.font_keys.insert(face_info.clone(), font_key);
-    //     self.fonts.insert(face_info.clone(), font_data);
-    // }
-
-    pub fn get_font(&mut self, face_info: &FaceInfo) -> &[u8] {
-        self.fonts.entry(face_info.clone()).or_insert_with(||std::fs::read(&face_info.path).unwrap())
-    }
-
-    pub fn get_font_instance_key(&mut self, face_info: &FaceInfo, font_size: u8) -> Option<FontInstanceKey>{
-        self.font_instance_keys.get(&(face_info.clone(), font_size)).map(|k| k.to_owned())
-    }
 }
 
 pub struct LayoutContext {
     font_cache: FontCache,
+    font_database: FontDatabase,
     // image_cache: ImageCache,
     viewport_size: Size<Au>,
     pub webrender_api: webrender::RenderApi,
@@ -58,6 +115,7 @@ impl LayoutContext {
 
         LayoutContext {
             font_cache: FontCache::new(),
+            font_database: FontDatabase::new(),
             // image_cache: ImageCache::new(),
             viewport_size,
             webrender_api,
@@ -65,12 +123,44 @@ impl LayoutContext {
         }
     }
 
+    /// The installed face that best matches a family list, weight and style.
+    pub fn resolve_font(&mut self, families: &str, weight: Weight, style: FontStyle) -> FaceInfo {
+        self.font_database.resolve(families, weight, style)
+    }
+
+    /// Whether `face_info` has glyphs for all of `text`. Spaces and controls
+    /// are not drawn, so need none.
+    pub fn covers(&mut self, face_info: &FaceInfo, text: &str) -> bool {
+        text.chars().all(|c| {
+            if c.is_whitespace() || c.is_control() {
+                return true;
+            }
+            if let Some(&covered) = self.font_cache.coverage.get(face_info).and_then(|chars| chars.get(&c)) {
+                return covered;
+            }
+            let font = self.get_font(face_info);
+            let covered = ttf_parser::Face::parse(font, face_info.index)
+                .is_ok_and(|face| face.glyph_index(c).is_some());
+            self.font_cache.coverage.entry(face_info.clone()).or_default().insert(c, covered);
+            covered
+        })
+    }
+
+    /// An installed face that covers `text`, when the style's face does not.
+    pub fn fallback_font(&mut self, text: &str, weight: Weight, style: FontStyle) -> Option<FaceInfo> {
+        self.font_database.fallback(text, weight, style)
+    }
+
     pub fn get_font(&mut self, face_info: &FaceInfo) -> &[u8] {
         // let font_cache = self.font_cache.borrow();
         // font_cache.fonts.get(face_info).map(|x| x.as_slice());
 
         if  self.font_cache.fonts.get(face_info).is_none() {
-            let font_data = std::fs::read(&face_info.path).unwrap();
+            let font_data = std::fs::read(&face_info.path).unwrap_or_else(|err| {
+                // Lay out with the bundled font rather than not at all.
+                eprintln!("wedit: failed to read {}: {err}", face_info.path.display());
+                DEFAULT_FONT_DATA.to_vec()
+            });
 
             let mut txn = Transaction::new();
             let font_key = self.webrender_api.generate_font_key();
@@ -83,23 +173,59 @@ impl LayoutContext {
         self.font_cache.fonts.get(face_info).unwrap()
     }
 
-    pub fn get_font_instance(&mut self, face_info: &FaceInfo, font_size: u8) -> FontInstanceKey {
-        // let Some(font_key) = self.font_cache.borrow().font_keys.get(face_info) else {
-        //     self.get_font(face_info);
-        // }
+    /// The instance of a face at `font_size` device pixels, created on first
+    /// use. The size is rounded to 1/64 of a pixel.
+    pub fn get_font_instance(
+        &mut self,
+        face_info: &FaceInfo,
+        font_size: f32,
+        options: FontRenderOptions,
+    ) -> FontInstanceKey {
         if self.font_cache.font_keys.get(face_info).is_none() {
             self.get_font(face_info);
         }
-        if let Some(instance_key) = self.font_cache.font_instance_keys.get(&(face_info.clone(), font_size)) {
-            return *instance_key;
-        } else {
-            let font_key = self.font_cache.font_keys.get(face_info).unwrap();
-            let mut txn = Transaction::new();
-            let instance_key = self.webrender_api.generate_font_instance_key();
-            txn.add_font_instance(instance_key, *font_key, font_size as f32, None, None, vec![]);
+        let generation = self.font_cache.generation;
+        let size = FontSize::from_px(font_size);
+        let cache_key = (face_info.clone(), size, options);
+        if let Some(instance) = self.font_cache.font_instances.get_mut(&cache_key) {
+            instance.last_used = generation;
+            return instance.key;
+        }
+
+        let font_key = self.font_cache.font_keys.get(face_info).unwrap();
+        let (instance_options, platform_options) = options.to_webrender();
+        let mut txn = Transaction::new();
+        let instance_key = self.webrender_api.generate_font_instance_key();
+        txn.add_font_instance(
+            instance_key,
+            *font_key,
+            size.to_px(),
+            Some(instance_options),
+            platform_options,
+            vec![]
+        );
+        self.webrender_api.send_transaction(self.document_id, txn);
+        self.font_cache.font_instances.insert(cache_key, FontInstance { key: instance_key, last_used: generation });
+        instance_key
+    }
+
+    /// Delete the font instances that recent display lists have not used,
+    /// e.g. the sizes before a zoom. Call this after sending each display list.
+    pub fn evict_font_instances(&mut self) {
+        let generation = self.font_cache.generation;
+        let mut txn = Transaction::new();
+        let mut evicted = false;
+        self.font_cache.font_instances.retain(|_, instance| {
+            let keep = generation - instance.last_used < FONT_INSTANCE_LIFETIME;
+            if !keep {
+                txn.delete_font_instance(instance.key);
+                evicted = true;
+            }
+            keep
+        });
+        if evicted {
             self.webrender_api.send_transaction(self.document_id, txn);
-            self.font_cache.font_instance_keys.insert((face_info.clone(), font_size), instance_key);
-            instance_key
         }
+        self.font_cache.generation += 1;
     }
 }
@@ -1,13 +1,29 @@
+use std::ops::Range;
+
 use app_units::Au;
-use webrender_api::PipelineId;
+use webrender_api::{ExternalScrollId, HasScrollLinkedEffect, PipelineId, SpaceAndClipInfo, SpatialTreeItemKey};
+use webrender_api::units::LayoutVector2D;
 
-use super::{fragment::{TextFragment, BoxFragment, Fragment}, Rect, Point, context::LayoutContext};
+use super::{caret::{Caret, CaretStyle}, fragment::{TextFragment, BoxFragment, BoxKind, Fragment}, Rect, Point, Size, context::{FontRenderOptions, LayoutContext}};
 
 pub struct DisplayListBuilder<'a> {
     pub scale_factor: f32,
     space_and_clip: webrender_api::SpaceAndClipInfo,
+    /// The spaces the scroll frames pushed were pushed in.
+    parents: Vec<webrender_api::SpaceAndClipInfo>,
+    /// The outermost scroll frame pushed, and the size of its content.
+    document: Option<(Rect<Au>, Size<Au>)>,
+    /// Scroll frame to build gutters in, which follows the document up and
+    /// down but not sideways.
+    pub gutter_scroll_id: Option<ExternalScrollId>,
     context: &'a mut LayoutContext,
     pub wr: webrender_api::DisplayListBuilder,
+    /// Chars of the buffer to paint as selected.
+    pub selection: Range<usize>,
+    pub selection_color: webrender_api::ColorF,
+    pub caret_color: webrender_api::ColorF,
+    pub gutter_background: webrender_api::ColorF,
+    pub font_render_options: FontRenderOptions,
 }
 
 impl<'a> DisplayListBuilder<'a> {
@@ -15,8 +31,16 @@ impl<'a> DisplayListBuilder<'a> {
         DisplayListBuilder {
             scale_factor,
             space_and_clip: webrender_api::SpaceAndClipInfo::root_scroll(pipeline_id),
+            parents: Vec::new(),
+            document: None,
+            gutter_scroll_id: None,
             context,
             wr: webrender_api::DisplayListBuilder::new(pipeline_id),
+            selection: 0..0,
+            selection_color: webrender_api::ColorF::new(0.7, 0.8, 1.0, 1.0),
+            caret_color: webrender_api::ColorF::BLACK,
+            gutter_background: webrender_api::ColorF::WHITE,
+            font_render_options: FontRenderOptions::default(),
         }
     }
 
@@ -28,52 +52,182 @@ impl<'a> DisplayListBuilder<'a> {
             flags: webrender_api::PrimitiveFlags::default(),
         }
     }
+
+    /// Start a scrollable area covering `frame_rect`. Items pushed after this
+    /// are `content_size` worth of content scrolled inside it, and are moved
+    /// by setting the offset of `scroll_id` without building a new display list.
+    pub fn push_scroll_frame(&mut self, scroll_id: ExternalScrollId, frame_rect: Rect<Au>, content_size: Size<Au>) {
+        if self.parents.is_empty() {
+            self.document = Some((frame_rect, content_size));
+        }
+        let parent = self.space_and_clip.spatial_id;
+        let content_rect = Rect {
+            origin: frame_rect.origin,
+            size: content_size,
+        };
+        let spatial_id = self.wr.define_scroll_frame(
+            parent,
+            scroll_id,
+            content_rect.to_layout(self.scale_factor),
+            frame_rect.to_layout(self.scale_factor),
+            LayoutVector2D::zero(),
+            0,
+            HasScrollLinkedEffect::No,
+            SpatialTreeItemKey::new(scroll_id.0, 0),
+        );
+        // The clip belongs to the parent space, so that it stays put while the
+        // content scrolls.
+        let clip_id = self.wr.define_clip_rect(parent, frame_rect.to_layout(self.scale_factor));
+        let clip_chain_id = self.wr.define_clip_chain(None, [clip_id]);
+        let parent = std::mem::replace(&mut self.space_and_clip, SpaceAndClipInfo {
+            spatial_id,
+            clip_chain_id,
+        });
+        self.parents.push(parent);
+    }
+
+    /// End the scrollable area pushed last, so that the items pushed after
+    /// this are in the space it was pushed in.
+    pub fn pop_scroll_frame(&mut self) {
+        if let Some(parent) = self.parents.pop() {
+            self.space_and_clip = parent;
+        }
+    }
+
+    /// Build a gutter at `rect` of the document with `build`, over its
+    /// background. Without a gutter scroll frame, the gutter scrolls along
+    /// with the document.
+    pub fn build_gutter(&mut self, rect: Rect<Au>, build: impl FnOnce(&mut Self)) {
+        let (Some(scroll_id), Some((document, content_size)), Some(&root)) =
+            (self.gutter_scroll_id, self.document, self.parents.first())
+        else {
+            let common = self.common_properties(rect);
+            self.wr.push_rect(&common, rect.to_layout(self.scale_factor), self.gutter_background);
+            build(self);
+            return;
+        };
+        // The gutter's frame is beside the document's, not inside it.
+        let document_space = std::mem::replace(&mut self.space_and_clip, root);
+        let frame_rect = Rect {
+            origin: Point::new(rect.origin.i, document.origin.b),
+            size: Size { width: rect.size.width, height: document.size.height },
+        };
+        let content_size = Size { width: rect.size.width, height: content_size.height };
+        self.push_scroll_frame(scroll_id, frame_rect, content_size);
+
+        let background = Rect { origin: frame_rect.origin, size: content_size };
+        let common = self.common_properties(background);
+        self.wr.push_rect(&common, background.to_layout(self.scale_factor), self.gutter_background);
+        build(self);
+
+        self.pop_scroll_frame();
+        self.space_and_clip = document_space;
+    }
+
+    /// Paint the caret over the text laid out in `root`.
+    pub fn push_caret(&mut self, root: &BoxFragment, caret: &Caret, containing_block: Rect<Au>) {
+        let Some(rect) = root.caret_rect(caret) else {
+            return;
+        };
+        let rect = rect.translate(containing_block.origin);
+        // A block caret sits on top of its glyph, so keep the glyph visible.
+        let alpha = if caret.style == CaretStyle::Block { 0.5 } else { 1.0 };
+        let color = webrender_api::ColorF { a: self.caret_color.a * alpha, ..self.caret_color };
+        let common = self.common_properties(rect);
+        self.wr.push_rect(&common, rect.to_layout(self.scale_factor), color);
+    }
 }
 
 impl BoxFragment {
     pub fn build_display_list(&self, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+        // Selections go under all of the text, as the selected line break at
+        // the end of a row may reach under the glyphs beside it.
+        self.build_selections(builder, containing_block);
+        self.build_text(builder, containing_block);
+
+        // builder.wr.push_rect(
+        //     &builder.common_properties(containing_block),
+        //     containing_block.to_layout(builder.scale_factor),
+        //     webrender_api::ColorF::new(1.0, 0.0, 0.0, 1.0)
+        // );
+    }
+
+    fn content_rect(&self, containing_block: Rect<Au>) -> Rect<Au> {
         // FIXME: build for margins.
-        let containing_block = Rect {
+        Rect {
             origin: containing_block.origin + self.rect.origin,
             size: self.rect.size
-        };
+        }
+    }
 
+    fn build_selections(&self, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+        // Line numbers cannot be selected.
+        if self.kind == BoxKind::Gutter {
+            return;
+        }
+        let containing_block = self.content_rect(containing_block);
+        let mut text_fragments = Vec::new();
         for fragment in &self.children {
             match fragment {
                 Fragment::Text(text_fragment) => {
-                    text_fragment.build_display_list(builder, containing_block);
-                }
-                Fragment::Box(box_fragment) => {
-                    box_fragment.build_display_list(builder, containing_block);
+                    text_fragment.build_selection(builder, text_fragment.rect.translate(containing_block.origin));
+                    text_fragments.push(text_fragment);
                 }
+                Fragment::Box(box_fragment) => box_fragment.build_selections(builder, containing_block),
             }
         }
 
-        // builder.wr.push_rect(
-        //     &builder.common_properties(containing_block),
-        //     containing_block.to_layout(builder.scale_factor),
-        //     webrender_api::ColorF::new(1.0, 0.0, 0.0, 1.0)
-        // );
+        // Show that the line break is selected too, past the visual end of
+        // the row of fragments it ends.
+        let selection = builder.selection.clone();
+        for row in text_fragments.chunk_by(|a, b| a.rect.origin.b == b.rect.origin.b) {
+            let row_end = row.iter().map(|fragment| fragment.range.end).max().unwrap_or(0);
+            let Some(last) = row.iter().max_by_key(|fragment| fragment.rect.origin.i + fragment.rect.size.width) else {
+                continue;
+            };
+            if selection.start >= selection.end || selection.start > row_end || selection.end <= row_end {
+                continue;
+            }
+            let bounds = last.rect.translate(containing_block.origin);
+            let rect = Rect {
+                origin: Point::new(bounds.origin.i + bounds.size.width, bounds.origin.b),
+                size: Size { width: Au::from_f32_px(last.font_size / 2.0), height: bounds.size.height },
+            };
+            let common = builder.common_properties(rect);
+            builder.wr.push_rect(&common, rect.to_layout(builder.scale_factor), builder.selection_color);
+        }
+    }
+
+    fn build_text(&self, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+        let containing_block = self.content_rect(containing_block);
+        for fragment in &self.children {
+            match fragment {
+                Fragment::Text(text_fragment) => text_fragment.build_glyphs(builder, containing_block),
+                Fragment::Box(box_fragment) => box_fragment.build_text(builder, containing_block),
+            }
+        }
     }
 }
 
 impl TextFragment {
-    pub fn build_display_list(&self, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+    fn build_glyphs(&self, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
         let font_key = builder.context.get_font_instance(
             &self.face_info,
-            (self.font_size * builder.scale_factor) as u8
+            self.font_size * builder.scale_factor,
+            builder.font_render_options
         );
-        let color = webrender_api::ColorF::new(0.0, 0.0, 0.0, 1.0);
-        // let color = webrender_api::ColorF::new(0.0, 0.0, 0.0, 1.0);
         let common = builder.common_properties(containing_block);
         let bounds = self.rect.translate(containing_block.origin);
 
         let mut glyphs = Vec::new();
 
-        let mut cur_i = Au(0);
+        let mut cur_i = bounds.origin.i;
         for glyph in &self.glyphs {
-            // The y axis of a glyph starts from downside up.
-            let point_au = Point::new(cur_i, bounds.origin.b + bounds.size.height) + glyph.offset;
+            // Glyph offsets point up from the baseline, layout points down.
+            let point_au = Point::new(
+                cur_i + glyph.offset.i,
+                bounds.origin.b + self.baseline - glyph.offset.b
+            );
             glyphs.push(webrender_api::GlyphInstance {
                 index: glyph.glyph_id,
                 point: point_au.to_layout(builder.scale_factor),
@@ -81,6 +235,27 @@ impl TextFragment {
             cur_i += glyph.advance;
         }
 
-        builder.wr.push_text(&common, bounds.to_layout(builder.scale_factor), &glyphs, font_key, color, None)
+        builder.wr.push_text(&common, bounds.to_layout(builder.scale_factor), &glyphs, font_key, self.color, None)
+    }
+
+    fn build_selection(&self, builder: &mut DisplayListBuilder, bounds: Rect<Au>) {
+        let selection = &builder.selection;
+        if selection.start >= selection.end ||
+            selection.end < self.range.start || selection.start > self.range.end {
+            return;
+        }
+        let from = self.inline_offset(selection.start.max(self.range.start));
+        let to = self.inline_offset(selection.end.min(self.range.end));
+        let (start, end) = (from.min(to), from.max(to));
+        if start == end {
+            return;
+        }
+        let rect = Rect {
+            origin: Point::new(bounds.origin.i + start, bounds.origin.b),
+            size: Size { width: end - start, height: bounds.size.height },
+        };
+        let color = builder.selection_color;
+        let common = builder.common_properties(rect);
+        builder.wr.push_rect(&common, rect.to_layout(builder.scale_factor), color);
     }
 }
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use fontdb::{Database, Family, Query, Source};
+use ttf_parser::{Style as FontStyle, Weight};
+
+use crate::resources::resource_dir;
+
+use super::inline::FaceInfo;
+
+/// The font bundled with the editor, used when nothing else matches.
+const DEFAULT_FONT: &str = "FiraCode-Regular.otf";
+
+/// The bundled font, built in for when its file or another font cannot be
+/// read.
+pub const DEFAULT_FONT_DATA: &[u8] = include_bytes!("../../resources/FiraCode-Regular.otf");
+
+/// The fonts installed on the system, found in the platform's font
+/// directories and, on Linux and BSDs, the fontconfig configuration.
+pub struct FontDatabase {
+    database: Database,
+    /// Faces already resolved, keyed by family list, weight and style.
+    resolved: HashMap<(String, u16, FontStyle), FaceInfo>,
+    /// Fallback faces already found, keyed by text, weight and style.
+    fallbacks: HashMap<(String, u16, FontStyle), Option<FaceInfo>>,
+    /// Every face in the database, for finding fallbacks among.
+    candidates: Vec<Candidate>,
+    /// Indices into `candidates` in the order they are tried as fallbacks
+    /// for a weight and style, best first.
+    fallback_orders: HashMap<(u16, FontStyle), Vec<usize>>,
+}
+
+/// A face that may stand in for the one a style asks for.
+struct Candidate {
+    id: fontdb::ID,
+    style: fontdb::Style,
+    weight: u16,
+    monospaced: bool,
+    /// Sorted ranges of the chars the face maps to glyphs, read from its
+    /// file the first time it is tried.
+    coverage: Option<Vec<Range<u32>>>,
+}
+
+impl Candidate {
+    fn covers(&mut self, database: &Database, text: &str) -> bool {
+        let coverage = self.coverage.get_or_insert_with(|| {
+            database.with_face_data(self.id, read_coverage).unwrap_or_default()
+        });
+        text.chars().all(|c| {
+            let c = c as u32;
+            let i = coverage.partition_point(|range| range.end <= c);
+            coverage.get(i).is_some_and(|range| range.contains(&c))
+        })
+    }
+}
+
+impl FontDatabase {
+    pub fn new() -> FontDatabase {
+        let mut database = Database::new();
+        database.load_system_fonts();
+        // The bundled font can also be asked for by name.
+        database.load_fonts_dir(resource_dir());
+        let candidates = database
+            .faces()
+            .map(|face| Candidate {
+                id: face.id,
+                style: face.style,
+                weight: face.weight.0,
+                monospaced: face.monospaced,
+                coverage: None,
+            })
+            .collect();
+        FontDatabase {
+            database,
+            resolved: HashMap::new(),
+            fallbacks: HashMap::new(),
+            candidates,
+            fallback_orders: HashMap::new(),
+        }
+    }
+
+    /// The face that best matches `families`, a comma separated list of family
+    /// names and generic families as in the CSS `font-family` property.
+    pub fn resolve(&mut self, families: &str, weight: Weight, style: FontStyle) -> FaceInfo {
+        let key = (families.to_owned(), weight.to_number(), style);
+        if let Some(face_info) = self.resolved.get(&key) {
+            return face_info.clone();
+        }
+        let face_info = self.query(families, weight, style).unwrap_or_else(default_face);
+        self.resolved.insert(key, face_info.clone());
+        face_info
+    }
+
+    /// A face with a glyph for every char of `text`, for text the face the
+    /// style asks for lacks. Faces closest to `weight` and `style` are tried
+    /// first, then monospaced ones. The search is cached by the whole text,
+    /// usually a grapheme cluster, as its other chars may not be covered by a
+    /// face that has the first. Each face's file is read once, the first time
+    /// it is tried.
+    pub fn fallback(&mut self, text: &str, weight: Weight, style: FontStyle) -> Option<FaceInfo> {
+        if text.is_empty() {
+            return None;
+        }
+        let key = (text.to_owned(), weight.to_number(), style);
+        if let Some(face_info) = self.fallbacks.get(&key) {
+            return face_info.clone();
+        }
+
+        let candidates = &mut self.candidates;
+        let order = self.fallback_orders.entry((key.1, style)).or_insert_with(|| {
+            let style = to_fontdb_style(style);
+            let mut order: Vec<usize> = (0..candidates.len()).collect();
+            order.sort_by_key(|&i| {
+                let candidate = &candidates[i];
+                (candidate.style != style, candidate.weight.abs_diff(weight.to_number()), !candidate.monospaced)
+            });
+            order
+        });
+        let face_info = order
+            .iter()
+            .find(|&&i| candidates[i].covers(&self.database, text))
+            .and_then(|&i| face_info(self.database.face(candidates[i].id)?));
+        self.fallbacks.insert(key, face_info.clone());
+        face_info
+    }
+
+    fn query(&self, families: &str, weight: Weight, style: FontStyle) -> Option<FaceInfo> {
+        let families: Vec<Family> = families
+            .split(',')
+            .map(|family| family.trim().trim_matches(|c| c == '"' || c == '\''))
+            .filter(|family| !family.is_empty())
+            .map(|family| match family {
+                "serif" => Family::Serif,
+                "sans-serif" => Family::SansSerif,
+                "monospace" => Family::Monospace,
+                "cursive" => Family::Cursive,
+                "fantasy" => Family::Fantasy,
+                name => Family::Name(name),
+            })
+            .collect();
+        let query = Query {
+            families: &families,
+            weight: fontdb::Weight(weight.to_number()),
+            style: to_fontdb_style(style),
+            ..Query::default()
+        };
+        face_info(self.database.face(self.database.query(&query)?)?)
+    }
+}
+
+fn face_info(face: &fontdb::FaceInfo) -> Option<FaceInfo> {
+    let path = match &face.source {
+        Source::File(path) | Source::SharedFile(path, _) => path.clone(),
+        // Only fonts loaded from files are added to the database.
+        Source::Binary(_) => return None,
+    };
+    Some(FaceInfo { path, index: face.index })
+}
+
+/// Sorted ranges of the chars a face maps to glyphs.
+fn read_coverage(data: &[u8], index: u32) -> Vec<Range<u32>> {
+    let Ok(face) = ttf_parser::Face::parse(data, index) else {
+        return Vec::new();
+    };
+    let mut chars = Vec::new();
+    for subtable in face.tables().cmap.iter().flat_map(|cmap| cmap.subtables) {
+        if subtable.is_unicode() {
+            subtable.codepoints(|c| {
+                if subtable.glyph_index(c).is_some_and(|glyph| glyph.0 != 0) {
+                    chars.push(c);
+                }
+            });
+        }
+    }
+    chars.sort_unstable();
+    chars.dedup();
+    let mut coverage: Vec<Range<u32>> = Vec::new();
+    for c in chars {
+        match coverage.last_mut() {
+            Some(range) if range.end == c => range.end += 1,
+            _ => coverage.push(c..c + 1),
+        }
+    }
+    coverage
+}
+
+fn to_fontdb_style(style: FontStyle) -> fontdb::Style {
+    match style {
+        FontStyle::Normal => fontdb::Style::Normal,
+        FontStyle::Italic => fontdb::Style::Italic,
+        FontStyle::Oblique => fontdb::Style::Oblique,
+    }
+}
+
+/// The bundled font.
+pub fn default_face() -> FaceInfo {
+    FaceInfo { path: resource_dir().join(DEFAULT_FONT), index: 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_merges_consecutive_chars() {
+        let coverage = read_coverage(DEFAULT_FONT_DATA, 0);
+        assert!(coverage.windows(2).all(|ranges| ranges[0].end < ranges[1].start));
+        let covers = |c: char| coverage.iter().any(|range| range.contains(&(c as u32)));
+        assert!(('a'..='z').all(covers));
+        assert!(coverage.iter().any(|range| range.contains(&('a' as u32)) && range.contains(&('z' as u32))));
+        assert!(!covers('\u{10fffd}'));
+    }
+}
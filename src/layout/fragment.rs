@@ -1,12 +1,26 @@
+use std::ops::Range;
+
 use app_units::Au;
+use webrender_api::ColorF;
 
-use super::{Rect, Sides, inline::{FaceInfo, GlyphInfo}};
+use super::{Rect, Sides, inline::{FaceInfo, FontMetrics, GlyphInfo}};
 
 pub struct TextFragment {
     pub rect: Rect<Au>,
     pub face_info: FaceInfo,
     pub font_size: f32,
     pub glyphs: Vec<GlyphInfo>,
+    /// Chars of the buffer covered by this fragment.
+    pub range: Range<usize>,
+    /// The text of `range`.
+    pub text: String,
+    /// Whether the text runs right to left, with `glyphs` still in visual order.
+    pub rtl: bool,
+    pub color: ColorF,
+    pub metrics: FontMetrics,
+    /// Distance from the top of the fragment to the baseline, shared by the
+    /// fragments on a line so that fallback fonts line up.
+    pub baseline: Au,
     // pub glyphs: Vec<GlyphInstance>
 }
 
@@ -25,10 +39,32 @@ pub struct ReplacedFragment {
     pub intrisic_height: Au,
 }
 
+/// What a box holds, which decides how it is painted and whether the caret
+/// and the pointer can reach into it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxKind {
+    /// Buffer text, or boxes holding it.
+    Content,
+    /// Line numbers and markers beside the text. They are not part of the
+    /// buffer, and stay at the side when the text scrolls sideways.
+    Gutter,
+}
+
 pub struct BoxFragment {
     pub rect: Rect<Au>,
     pub margin: Sides<Au>,
     pub children: Vec<Fragment>,
+    pub kind: BoxKind,
+}
+
+impl BoxFragment {
+    /// The gutter among the children, if there is one.
+    pub fn gutter(&self) -> Option<&BoxFragment> {
+        self.children.iter().find_map(|fragment| match fragment {
+            Fragment::Box(box_fragment) if box_fragment.kind == BoxKind::Gutter => Some(box_fragment),
+            _ => None,
+        })
+    }
 }
 
 pub enum Fragment {
@@ -0,0 +1,278 @@
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+use std::rc::Rc;
+
+use app_units::Au;
+use unicode_bidi::Level;
+use webrender_api::ColorF;
+
+use crate::buffer::Buffer;
+use crate::theme::Theme;
+
+use super::context::LayoutContext;
+use super::display_list::DisplayListBuilder;
+use super::fragment::{BoxFragment, BoxKind, Fragment, TextFragment};
+use super::inline::{column_width, TextRun};
+use super::line_index::LineIndex;
+use super::widget::{Text, TextStyle, Widget, WidgetEvent};
+use super::{Point, Rect, Sides, Size};
+
+/// Columns left of the line numbers, for diagnostic markers.
+const DIAGNOSTIC_COLUMNS: i32 = 2;
+/// Columns right of the line numbers, for fold markers and a gap before the
+/// text.
+const FOLD_COLUMNS: i32 = 2;
+/// Line numbers are padded to at least this many digits, so that the text does
+/// not move over every time a short file grows by a digit.
+const MIN_DIGITS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineNumbers {
+    /// Every line shows its own number.
+    Absolute,
+    /// Lines show how far they are from the line with the caret, which shows
+    /// its own number.
+    Relative,
+}
+
+/// The column of the gutter a marker goes in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerLane {
+    /// Left of the line numbers, for errors and warnings.
+    Diagnostic,
+    /// Right of the line numbers, for folded blocks.
+    Fold,
+}
+
+/// A symbol beside a line of the text, like an arrow on a folded block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub line: usize,
+    pub symbol: char,
+    pub color: ColorF,
+}
+
+/// Right-aligned numbers of the lines of a text, with lanes for markers on
+/// either side of them.
+///
+/// The gutter shares the line heights of the text it numbers, so it has to be
+/// laid out after it, and only numbers the first row of wrapped lines.
+pub struct Gutter {
+    pub buffer: Rc<RefCell<Buffer>>,
+    /// The text's style, in the color of line numbers.
+    style: TextStyle,
+    /// Color of the number of the line with the caret.
+    active_color: ColorF,
+    line_index: Rc<RefCell<LineIndex>>,
+    zoom: Cell<f32>,
+    /// Where the caret is in the text.
+    caret: Cell<Option<usize>>,
+    line_numbers: Cell<LineNumbers>,
+    /// Markers of each lane, sorted by line.
+    diagnostic_markers: RefCell<Vec<Marker>>,
+    fold_markers: RefCell<Vec<Marker>>,
+}
+
+impl Gutter {
+    /// A gutter numbering the lines of `text`, colored with `theme`.
+    pub fn new(text: &Text, theme: &Theme) -> Gutter {
+        Gutter {
+            buffer: text.buffer.clone(),
+            style: TextStyle {
+                color: theme.gutter_foreground,
+                ..text.style.clone()
+            },
+            active_color: theme.gutter_active_foreground,
+            line_index: text.line_index(),
+            zoom: Cell::new(1.0),
+            caret: Cell::new(None),
+            line_numbers: Cell::new(LineNumbers::Absolute),
+            diagnostic_markers: RefCell::new(Vec::new()),
+            fold_markers: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn set_theme(&mut self, theme: &Theme) {
+        self.style.color = theme.gutter_foreground;
+        self.active_color = theme.gutter_active_foreground;
+    }
+
+    /// Scale the numbers by `zoom`, as the text is.
+    pub fn set_zoom(&self, zoom: f32) {
+        self.zoom.set(zoom);
+    }
+
+    /// Move the caret, which decides the line numbered from in relative mode.
+    pub fn set_caret(&self, position: Option<usize>) {
+        self.caret.set(position);
+    }
+
+    pub fn set_line_numbers(&self, line_numbers: LineNumbers) {
+        self.line_numbers.set(line_numbers);
+    }
+
+    /// Replace the markers in `lane`.
+    pub fn set_markers(&self, lane: MarkerLane, mut markers: Vec<Marker>) {
+        markers.sort_by_key(|marker| marker.line);
+        match lane {
+            MarkerLane::Diagnostic => *self.diagnostic_markers.borrow_mut() = markers,
+            MarkerLane::Fold => *self.fold_markers.borrow_mut() = markers,
+        }
+    }
+
+    fn font_size(&self) -> f32 {
+        self.style.font_size * self.zoom.get()
+    }
+
+    fn line_height(&self) -> f32 {
+        self.style.line_height * self.zoom.get()
+    }
+
+    fn digits(&self) -> usize {
+        self.buffer.borrow().len_lines().to_string().len().max(MIN_DIGITS)
+    }
+
+    /// Shape `text` on a line of its own, with a fallback font for symbols
+    /// the gutter's font has no glyphs for.
+    fn shape(&self, text: &str, color: ColorF, context: &mut LayoutContext) -> TextFragment {
+        let line_height = Au::from_f32_px(self.line_height());
+        let primary = context.resolve_font(&self.style.font_family, self.style.font_weight, self.style.font_style);
+        let face_info = if context.covers(&primary, text) {
+            primary
+        } else {
+            context.fallback_font(text, self.style.font_weight, self.style.font_style).unwrap_or(primary)
+        };
+        let run = TextRun {
+            text: text.to_owned(),
+            offset: 0,
+            face_info,
+            font_size: self.font_size(),
+            color,
+            script: rustybuzz::script::LATIN,
+            features: self.style.font_features.clone(),
+            level: Level::ltr(),
+            rtl: false,
+        };
+        let glyphs = run.shape(context);
+        let metrics = run.metrics(context);
+        let width = glyphs.iter().fold(Au(0), |width, glyph| width + glyph.advance);
+        TextFragment {
+            rect: Rect {
+                origin: Point::new(Au(0), Au(0)),
+                size: Size { width, height: line_height },
+            },
+            face_info: run.face_info,
+            font_size: run.font_size,
+            glyphs,
+            range: 0..0,
+            text: String::new(),
+            rtl: false,
+            color,
+            metrics,
+            baseline: metrics.baseline(line_height),
+        }
+    }
+}
+
+impl Widget for Gutter {
+    /// Width of the gutter: the marker lanes, and room for the number of the
+    /// last line.
+    fn measure(&self, context: &mut LayoutContext) -> Option<Au> {
+        let digits = self.digits();
+        let face_info = context.resolve_font(&self.style.font_family, self.style.font_weight, self.style.font_style);
+        Some(column_width(context, &face_info, self.font_size()) * (DIAGNOSTIC_COLUMNS + digits as i32 + FOLD_COLUMNS))
+    }
+
+    /// Lay out the numbers and markers of the lines that intersect `visible`,
+    /// which the text beside the gutter has just been laid out for. The
+    /// fragment is as tall as the text.
+    fn layout(&self, context: &mut LayoutContext, _containing_block: Size<Au>, visible: Range<Au>) -> BoxFragment {
+        let face_info = context.resolve_font(&self.style.font_family, self.style.font_weight, self.style.font_style);
+        let column = column_width(context, &face_info, self.font_size());
+        let numbers_end = column * (DIAGNOSTIC_COLUMNS + self.digits() as i32);
+
+        let buffer = self.buffer.borrow();
+        let caret_line = self.caret.get().map(|caret| buffer.char_to_line(caret.min(buffer.len_chars())));
+        let mut line_index = self.line_index.borrow_mut();
+        line_index.update(&buffer, Au::from_f32_px(self.line_height()));
+        let diagnostic_markers = self.diagnostic_markers.borrow();
+        let fold_markers = self.fold_markers.borrow();
+
+        let mut children = Vec::new();
+        let first_line = line_index.line_at(visible.start);
+        for line_idx in first_line..buffer.len_lines() {
+            let top = line_index.offset(line_idx);
+            if top >= visible.end {
+                break;
+            }
+            if line_index.is_hidden(line_idx) {
+                continue;
+            }
+
+            let (number, color) = match (self.line_numbers.get(), caret_line) {
+                (_, Some(caret_line)) if caret_line == line_idx => (line_idx + 1, self.active_color),
+                (LineNumbers::Relative, Some(caret_line)) => (line_idx.abs_diff(caret_line), self.style.color),
+                _ => (line_idx + 1, self.style.color),
+            };
+            let mut fragment = self.shape(&number.to_string(), color, context);
+            // Right-align the number.
+            fragment.rect.origin = Point::new(numbers_end - fragment.rect.size.width, top);
+            children.push(Fragment::Text(fragment));
+
+            let lanes = [
+                (&diagnostic_markers, Au(0), column * DIAGNOSTIC_COLUMNS),
+                (&fold_markers, numbers_end, column * FOLD_COLUMNS),
+            ];
+            for (markers, lane_start, lane_width) in lanes {
+                let Ok(index) = markers.binary_search_by_key(&line_idx, |marker| marker.line) else {
+                    continue;
+                };
+                let marker = &markers[index];
+                let mut fragment = self.shape(&marker.symbol.to_string(), marker.color, context);
+                // Center the marker in its lane.
+                fragment.rect.origin = Point::new(lane_start + (lane_width - fragment.rect.size.width) / 2, top);
+                children.push(Fragment::Text(fragment));
+            }
+        }
+
+        BoxFragment {
+            rect: Rect {
+                origin: Point::new(Au(0), Au(0)),
+                size: Size {
+                    width: self.measure(context).unwrap_or_default(),
+                    height: line_index.total(),
+                },
+            },
+            margin: Sides::<Au>::zero(),
+            children,
+            kind: BoxKind::Gutter,
+        }
+    }
+
+    /// Gutters stay at the side when the text scrolls sideways, so they are
+    /// painted in a scroll frame of their own.
+    fn paint(&self, fragment: &BoxFragment, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+        let rect = fragment.rect.translate(containing_block.origin);
+        builder.build_gutter(rect, |builder| fragment.build_display_list(builder, containing_block));
+    }
+
+    fn handle_event(&mut self, event: &WidgetEvent) {
+        match *event {
+            WidgetEvent::Theme(theme) => self.set_theme(theme),
+            WidgetEvent::Zoom(zoom) => self.set_zoom(zoom),
+            WidgetEvent::Caret { buffer, position } => {
+                self.set_caret(Rc::ptr_eq(&self.buffer, buffer).then_some(position));
+            }
+            WidgetEvent::LineNumbers(line_numbers) => self.set_line_numbers(line_numbers),
+            WidgetEvent::Markers { buffer, lane, markers } if Rc::ptr_eq(&self.buffer, buffer) => {
+                self.set_markers(lane, markers.to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    /// Height of the text the gutter numbers, as of its last layout.
+    fn height(&self) -> Au {
+        self.line_index.borrow().total()
+    }
+}
@@ -0,0 +1,121 @@
+use app_units::Au;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::fragment::{BoxFragment, BoxKind, Fragment, TextFragment};
+use super::inline::GlyphInfo;
+use super::{Point, Rect};
+
+impl BoxFragment {
+    /// Find the buffer position closest to `point`, which is relative to this
+    /// fragment's containing block.
+    ///
+    /// Points outside of the text snap to the nearest line, and to the start or
+    /// end of that line. Gutters are not text, so points over them snap to the
+    /// text beside them.
+    pub fn hit_test(&self, point: Point<Au>) -> Option<usize> {
+        let point = Point::new(point.i - self.rect.origin.i, point.b - self.rect.origin.b);
+
+        let mut nearest: Option<(&Fragment, Au)> = None;
+        for fragment in &self.children {
+            let distance = match fragment {
+                Fragment::Box(box_fragment) if box_fragment.kind == BoxKind::Gutter => continue,
+                Fragment::Box(box_fragment) => {
+                    if box_fragment.contains(point) {
+                        return box_fragment.hit_test(point);
+                    }
+                    distance(&box_fragment.rect, point)
+                }
+                Fragment::Text(text_fragment) => distance(&text_fragment.rect, point),
+            };
+            if nearest.is_none_or(|(_, nearest_distance)| distance < nearest_distance) {
+                nearest = Some((fragment, distance));
+            }
+        }
+        match nearest? {
+            (Fragment::Text(text_fragment), _) => Some(text_fragment.offset_at(point.i - text_fragment.rect.origin.i)),
+            (Fragment::Box(box_fragment), _) => box_fragment.hit_test(point),
+        }
+    }
+
+    /// Whether `point`, which is relative to this fragment's containing block,
+    /// is over a gutter.
+    pub fn is_over_gutter(&self, point: Point<Au>) -> bool {
+        let point = Point::new(point.i - self.rect.origin.i, point.b - self.rect.origin.b);
+        self.children.iter().any(|fragment| match fragment {
+            Fragment::Box(box_fragment) if box_fragment.contains(point) => {
+                box_fragment.kind == BoxKind::Gutter || box_fragment.is_over_gutter(point)
+            }
+            _ => false,
+        })
+    }
+
+    fn contains(&self, point: Point<Au>) -> bool {
+        let rect = &self.rect;
+        point.i >= rect.origin.i && point.i < rect.origin.i + rect.size.width &&
+            point.b >= rect.origin.b && point.b < rect.origin.b + rect.size.height
+    }
+}
+
+/// Distance from `point` to `rect`. Vertical distance dominates, so that a
+/// point always hits the line it is on before a neighboring one.
+fn distance(rect: &Rect<Au>, point: Point<Au>) -> Au {
+    let axis_distance = |p: Au, start: Au, size: Au| {
+        if p < start {
+            start - p
+        } else if p >= start + size {
+            p - (start + size)
+        } else {
+            Au(0)
+        }
+    };
+    let di = axis_distance(point.i, rect.origin.i, rect.size.width);
+    let db = axis_distance(point.b, rect.origin.b, rect.size.height);
+    Au(db.0.saturating_mul(1 << 10).saturating_add(di.0))
+}
+
+impl TextFragment {
+    /// The buffer position nearest to `inline`, measured from the left of the
+    /// fragment.
+    ///
+    /// A cluster shaped from several grapheme clusters, such as a ligature,
+    /// has its advance shared evenly between them. A grapheme cluster is never
+    /// split, however many chars it has.
+    pub fn offset_at(&self, inline: Au) -> usize {
+        // Walk the glyphs in logical order, measuring from the logical start.
+        let (inline, glyphs): (Au, Vec<&GlyphInfo>) = if self.rtl {
+            (self.rect.size.width - inline, self.glyphs.iter().rev().collect())
+        } else {
+            (inline, self.glyphs.iter().collect())
+        };
+        // Byte offsets in `text` of its chars, and of its end.
+        let char_bytes: Vec<usize> = self.text.char_indices().map(|(i, _)| i).chain([self.text.len()]).collect();
+        let byte_at = |char_idx: usize| char_bytes[char_idx.saturating_sub(self.range.start).min(char_bytes.len() - 1)];
+        let mut position = Au(0);
+        let mut glyphs = glyphs.into_iter().peekable();
+        while let Some(glyph) = glyphs.next() {
+            let cluster = glyph.cluster;
+            let mut width = glyph.advance;
+            while let Some(next) = glyphs.next_if(|next| next.cluster == cluster) {
+                width += next.advance;
+            }
+            let cluster_end = glyphs.peek().map_or(self.range.end, |next| next.cluster);
+            let cluster_text = &self.text[byte_at(cluster)..byte_at(cluster_end).max(byte_at(cluster))];
+            // Chars from the start of the cluster to each grapheme in it.
+            let mut graphemes: Vec<usize> = cluster_text
+                .grapheme_indices(true)
+                .map(|(i, _)| cluster_text[..i].chars().count())
+                .collect();
+            if graphemes.is_empty() {
+                graphemes.push(0);
+            }
+            let grapheme_width = width / graphemes.len() as i32;
+            for (k, offset) in graphemes.into_iter().enumerate() {
+                if inline < position + grapheme_width * k as i32 + grapheme_width / 2 {
+                    return cluster + offset;
+                }
+            }
+            position += width;
+        }
+        self.range.end
+    }
+}
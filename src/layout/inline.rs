@@ -1,7 +1,10 @@
+use std::ops::Range;
 use std::path::PathBuf;
 
 use app_units::Au;
 use rustybuzz::{Script, UnicodeBuffer, Feature, Face};
+use unicode_bidi::Level;
+use webrender_api::ColorF;
 
 use super::context::LayoutContext;
 use super::fragment::TextFragment;
@@ -10,10 +13,49 @@ use super::{Point, Size};
 #[derive(Clone, Copy)]
 pub struct GlyphInfo {
     pub glyph_id: u32,
+    /// Char index in the buffer of the first char this glyph was shaped from.
+    pub cluster: usize,
     pub advance: Au,
     pub offset: Point<Au>,
 }
 
+/// Vertical metrics of a face at a font size.
+#[derive(Clone, Copy, Default)]
+pub struct FontMetrics {
+    /// Distance from the baseline up to the top of the tallest glyphs.
+    pub ascent: Au,
+    /// Distance from the baseline down to the bottom of the lowest glyphs.
+    pub descent: Au,
+    /// Extra space the font asks for between lines.
+    pub line_gap: Au,
+}
+
+impl FontMetrics {
+    /// Distance from the top of a line `line_height` tall to the baseline,
+    /// with the leading split evenly above and below the glyphs as in CSS.
+    pub fn baseline(&self, line_height: Au) -> Au {
+        let half_leading = (line_height - (self.ascent + self.descent)) / 2;
+        half_leading + self.ascent
+    }
+}
+
+/// Width of a column of text in `face_info` at `font_size`, for wrapping and
+/// indenting by a number of columns: the advance of a digit, as in the CSS
+/// `ch` unit.
+pub fn column_width(context: &mut LayoutContext, face_info: &FaceInfo, font_size: f32) -> Au {
+    let font_size = Au::from_f32_px(font_size);
+    let font = context.get_font(face_info);
+    let Ok(face) = ttf_parser::Face::parse(font, face_info.index) else {
+        // Half an em is about right for most fonts.
+        return font_size / 2;
+    };
+    let advance = face.glyph_index('0').and_then(|glyph| face.glyph_hor_advance(glyph));
+    match advance {
+        Some(advance) => font_size * advance as i32 / face.units_per_em() as i32,
+        None => font_size / 2,
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct FaceInfo {
     pub path: PathBuf,
@@ -22,28 +64,54 @@ pub struct FaceInfo {
 
 pub struct TextRun {
     pub text: String,
+    /// Char index in the buffer where `text` starts.
+    pub offset: usize,
     pub face_info: FaceInfo,
     pub font_size: f32,
+    pub color: ColorF,
     pub script: Script,
+    /// OpenType features to shape with, each over a byte range of `text`.
+    pub features: Vec<Feature>,
+    /// Bidi embedding level, for ordering runs on a line.
+    pub level: Level,
     pub rtl: bool,
 }
 
 impl TextRun {
+    pub fn metrics(&self, context: &mut LayoutContext) -> FontMetrics {
+        let font = context.get_font(&self.face_info);
+        let Ok(face) = ttf_parser::Face::parse(font, self.face_info.index) else {
+            return FontMetrics::default();
+        };
+        let font_size = Au::from_f32_px(self.font_size);
+        let upem = face.units_per_em() as i32;
+        let scale = |units: i16| font_size * units as i32 / upem;
+        FontMetrics {
+            ascent: scale(face.ascender()),
+            descent: scale(-face.descender()),
+            line_gap: scale(face.line_gap()),
+        }
+    }
+
     pub fn shape(&self, context: &mut LayoutContext) -> Vec<GlyphInfo> {
         let mut buffer = UnicodeBuffer::new();
-        let features: Vec<Feature> = Vec::new();
         let font = context.get_font(&self.face_info);
-        let face = Face::from_slice(font, self.face_info.index as u32).unwrap();
+        let Some(face) = Face::from_slice(font, self.face_info.index) else {
+            return Vec::new();
+        };
 
         buffer.push_str(&self.text);
         buffer.set_script(self.script);
         if self.rtl {
             buffer.set_direction(rustybuzz::Direction::RightToLeft);
         }
-        let glyph_buffer = rustybuzz::shape(&face, &features, buffer);
+        let glyph_buffer = rustybuzz::shape(&face, &self.features, buffer);
         let font_size = Au::from_f32_px(self.font_size);
         let upem = face.units_per_em();
 
+        // Clusters are byte offsets into `text`, but buffer positions are chars.
+        let char_starts: Vec<usize> = self.text.char_indices().map(|(i, _)| i).collect();
+
         let mut glyphs = Vec::new();
         let mut width = 0;
         let mut height = 0;
@@ -52,8 +120,12 @@ impl TextRun {
             .glyph_infos()
             .iter()
             .zip(glyph_buffer.glyph_positions()) {
+                let char_index = char_starts
+                    .binary_search(&(info.cluster as usize))
+                    .unwrap_or_else(|i| i);
                 let glyph = GlyphInfo {
                     glyph_id: info.glyph_id,
+                    cluster: self.offset + char_index,
                     advance: font_size * pos.x_advance / upem,
                     offset: Point {
                         i: font_size * pos.x_offset / upem,
@@ -68,6 +140,33 @@ impl TextRun {
     }
 }
 
+/// The visual order of the runs of a line with the given bidi levels, as
+/// indices into `levels` from left to right (rule L2 of UAX #9).
+pub fn visual_order(levels: &[Level]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    let (Some(lowest), Some(highest)) = (levels.iter().min(), levels.iter().max()) else {
+        return order;
+    };
+    // From the highest level down to the lowest odd one, reverse every
+    // sequence of runs at that level or above.
+    let lowest_odd = lowest.number() | 1;
+    for level in (lowest_odd..=highest.number()).rev() {
+        let mut i = 0;
+        while i < order.len() {
+            if levels[order[i]].number() < level {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < order.len() && levels[order[i]].number() >= level {
+                i += 1;
+            }
+            order[start..i].reverse();
+        }
+    }
+    order
+}
+
 pub struct Line {
     fragments: Vec<TextFragment>,
 }
@@ -75,5 +174,37 @@ pub struct Line {
 pub struct InlineState {
     pub lines: Vec<Line>,
     pub inline_position: Au,
-    pub containing_block: Size<Au>
+    /// Where the next line starts, from the top of the containing block.
+    pub block_position: Au,
+    pub containing_block: Size<Au>,
+    /// The part of the containing block to lay out. Lines outside it only
+    /// count towards the height.
+    pub visible: Range<Au>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(levels: &[u8]) -> Vec<usize> {
+        let levels: Vec<Level> = levels.iter().map(|&level| Level::new(level).unwrap()).collect();
+        visual_order(&levels)
+    }
+
+    #[test]
+    fn right_to_left_runs_are_reversed_between_left_to_right_ones() {
+        assert_eq!(order(&[0, 1, 1, 0]), [0, 2, 1, 3]);
+        assert_eq!(order(&[1, 1, 1]), [2, 1, 0]);
+        assert_eq!(order(&[0, 0]), [0, 1]);
+        assert_eq!(order(&[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn nested_levels_are_reversed_from_the_highest_down() {
+        // Left-to-right text, such as a number, inside right-to-left text
+        // keeps its order once both reversals are done.
+        assert_eq!(order(&[0, 1, 2, 1]), [0, 3, 2, 1]);
+        assert_eq!(order(&[1, 2, 2, 1]), [3, 1, 2, 0]);
+        assert_eq!(order(&[0, 2, 0]), [0, 1, 2]);
+    }
 }
@@ -0,0 +1,127 @@
+use app_units::Au;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::inline::GlyphInfo;
+
+/// Where a line of text may be wrapped, as buffer char positions a visual line
+/// can start at.
+pub struct LineBreaks {
+    /// Char index in the buffer where the text starts.
+    offset: usize,
+    chars: Vec<char>,
+    /// Break opportunities of the Unicode line breaking algorithm (UAX #14).
+    words: Vec<usize>,
+    /// Grapheme cluster boundaries, for tokens too long to fit on a line.
+    graphemes: Vec<usize>,
+}
+
+impl LineBreaks {
+    pub fn new(text: &str, offset: usize) -> LineBreaks {
+        let char_starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        let to_char = |byte: usize| offset + char_starts.binary_search(&byte).unwrap_or_else(|i| i);
+
+        let words = unicode_linebreak::linebreaks(text).map(|(byte, _)| to_char(byte)).collect();
+        let graphemes = text.grapheme_indices(true).skip(1).map(|(byte, _)| to_char(byte)).collect();
+        LineBreaks {
+            offset,
+            chars: text.chars().collect(),
+            words,
+            graphemes,
+        }
+    }
+
+    /// Split shaped glyphs, in logical order, into visual lines no wider than
+    /// `width` where possible, with the second and later lines `indent`
+    /// narrower. Returns the positions those lines start at.
+    ///
+    /// Whitespace at the end of a line hangs past `width`, and a line always
+    /// keeps at least one grapheme so that wrapping makes progress.
+    pub fn wrap(&self, glyphs: &[GlyphInfo], width: Au, indent: Au) -> Vec<usize> {
+        let mut line_starts = Vec::new();
+        let mut start = self.offset;
+        let mut len = Au(0);
+        let mut i = 0;
+        while i < glyphs.len() {
+            let glyph = glyphs[i];
+            if glyph.cluster > start && len + glyph.advance > width && !self.is_whitespace(glyph.cluster) {
+                start = self.line_end(start, glyph.cluster);
+                if start >= self.offset + self.chars.len() {
+                    break;
+                }
+                line_starts.push(start);
+                len = indent;
+                // Carry the glyphs after the break over to the new line.
+                i = glyphs.iter().position(|glyph| glyph.cluster >= start).unwrap_or(glyphs.len());
+                continue;
+            }
+            len += glyph.advance;
+            i += 1;
+        }
+        line_starts
+    }
+
+    /// Where to end a line that starts at `start` and overflows at `overflow`:
+    /// the last word break that fits, or else the last grapheme boundary.
+    fn line_end(&self, start: usize, overflow: usize) -> usize {
+        let fits = |position: &&usize| start < **position && **position <= overflow;
+        if let Some(&position) = self.words.iter().rev().find(fits) {
+            return position;
+        }
+        if let Some(&position) = self.graphemes.iter().rev().find(fits) {
+            return position;
+        }
+        self.graphemes
+            .iter()
+            .copied()
+            .find(|&position| position > start)
+            .unwrap_or(self.offset + self.chars.len())
+    }
+
+    fn is_whitespace(&self, position: usize) -> bool {
+        self.chars.get(position - self.offset).is_some_and(|c| c.is_whitespace())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::Point;
+
+    use super::*;
+
+    /// Wrap `text`, starting at `offset` in the buffer, as if every char were
+    /// shaped to a glyph one unit wide.
+    fn wrap(text: &str, offset: usize, width: i32, indent: i32) -> Vec<usize> {
+        let glyphs: Vec<GlyphInfo> = (0..text.chars().count())
+            .map(|i| GlyphInfo {
+                glyph_id: 0,
+                cluster: offset + i,
+                advance: Au(1),
+                offset: Point::new(Au(0), Au(0)),
+            })
+            .collect();
+        LineBreaks::new(text, offset).wrap(&glyphs, Au(width), Au(indent))
+    }
+
+    #[test]
+    fn lines_break_after_spaces() {
+        assert_eq!(wrap("hello world", 0, 8, 0), [6]);
+        assert_eq!(wrap("hello world", 100, 8, 0), [106]);
+        // The space hangs past the width rather than starting the next line.
+        assert_eq!(wrap("hello world", 0, 5, 0), [6]);
+        assert_eq!(wrap("hello world", 0, 11, 0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn words_longer_than_a_line_break_between_graphemes() {
+        assert_eq!(wrap("abcdefghijkl", 0, 5, 0), [5, 10]);
+        // A combining accent stays with its letter.
+        assert_eq!(wrap("abcde\u{301}fgh", 0, 5, 0), [4]);
+        assert_eq!(wrap("ab abcdefgh", 0, 5, 0), [3, 8]);
+    }
+
+    #[test]
+    fn later_lines_are_narrower_by_the_indent() {
+        assert_eq!(wrap("aaa bbb ccc ddd", 0, 8, 0), [8]);
+        assert_eq!(wrap("aaa bbb ccc ddd", 0, 8, 3), [8, 12]);
+    }
+}
@@ -0,0 +1,241 @@
+use std::ops::Range;
+
+use app_units::Au;
+
+use crate::buffer::Buffer;
+use crate::buffer::edits::Edit;
+
+/// Heights of the lines of a text, with prefix sums for finding the line at a
+/// given offset without laying out everything above it.
+///
+/// Lines that were never laid out keep the height they were given when the
+/// index grew, so the total is an estimate until the whole text has been seen.
+#[derive(Default)]
+pub struct LineIndex {
+    heights: Vec<Au>,
+    /// A Fenwick tree over `heights`, 1-based.
+    tree: Vec<i32>,
+    /// Revision of the buffer the heights are of the lines of.
+    revision: u64,
+    /// Ranges of lines folded away, sorted by start. They may nest.
+    hidden: Vec<Range<usize>>,
+}
+
+impl LineIndex {
+    /// Catch up with the edits made to `buffer`, moving the heights and hidden
+    /// ranges of the lines they did not touch along with those lines. Lines
+    /// the edits added get `height`.
+    pub fn update(&mut self, buffer: &Buffer, height: Au) {
+        let len = buffer.len_lines();
+        if self.revision == buffer.revision() && self.heights.len() == len {
+            return;
+        }
+        match buffer.edits_since(self.revision) {
+            Some(edits) => {
+                for edit in edits {
+                    self.splice(edit, height);
+                }
+            }
+            // Too far behind to tell where the lines went.
+            None => {
+                self.heights.clear();
+                self.hidden.clear();
+            }
+        }
+        self.revision = buffer.revision();
+        self.heights.resize(len, height);
+        self.rebuild();
+    }
+
+    /// Replace the lines after the first one `edit` touched with the lines
+    /// it left there.
+    fn splice(&mut self, edit: &Edit, height: Au) {
+        let first = (edit.start.line + 1).min(self.heights.len());
+        let removed_end = (edit.old_end.line + 1).clamp(first, self.heights.len());
+        let inserted = edit.new_end.line - edit.start.line;
+        self.heights.splice(first..removed_end, std::iter::repeat_n(height, inserted));
+
+        let shift = |line: usize| {
+            if line > edit.old_end.line {
+                line - edit.old_end.line + edit.new_end.line
+            } else {
+                line.min(edit.new_end.line + 1)
+            }
+        };
+        for lines in &mut self.hidden {
+            *lines = shift(lines.start)..shift(lines.end);
+        }
+        self.hidden.retain(|lines| !lines.is_empty());
+    }
+
+    /// Give every line `height`, e.g. when the line height changes. Hidden
+    /// lines stay hidden.
+    pub fn reset(&mut self, height: Au) {
+        self.heights.fill(height);
+        for line in self.hidden.iter().flat_map(Range::clone) {
+            if let Some(slot) = self.heights.get_mut(line) {
+                *slot = Au(0);
+            }
+        }
+        self.rebuild();
+    }
+
+    /// Hide the lines in `hidden` by taking away their height. Lines hidden
+    /// before and not in it get `height` until they are laid out again.
+    pub fn set_hidden(&mut self, mut hidden: Vec<Range<usize>>, height: Au) {
+        hidden.sort_by_key(|lines| lines.start);
+        if self.hidden == hidden {
+            return;
+        }
+        let len = self.heights.len();
+        let shown = std::mem::take(&mut self.hidden);
+        for line in shown.into_iter().flatten().filter(|&line| line < len) {
+            self.set_height(line, height);
+        }
+        for line in hidden.iter().flat_map(Range::clone).filter(|&line| line < len) {
+            self.set_height(line, Au(0));
+        }
+        self.hidden = hidden;
+    }
+
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.hidden.iter().any(|lines| lines.contains(&line))
+    }
+
+    pub fn height(&self, line: usize) -> Au {
+        self.heights[line]
+    }
+
+    pub fn set_height(&mut self, line: usize, height: Au) {
+        let delta = (height - self.heights[line]).0;
+        if delta == 0 {
+            return;
+        }
+        self.heights[line] = height;
+        let mut i = line + 1;
+        while i < self.tree.len() {
+            self.tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Offset of the top of `line`, which may be `len()` for the end of the text.
+    pub fn offset(&self, line: usize) -> Au {
+        let mut sum = 0;
+        let mut i = line.min(self.heights.len());
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+        Au(sum)
+    }
+
+    pub fn total(&self) -> Au {
+        self.offset(self.heights.len())
+    }
+
+    /// The line containing `offset`, clamped to the first and last lines.
+    pub fn line_at(&self, offset: Au) -> usize {
+        if self.heights.is_empty() || offset <= Au(0) {
+            return 0;
+        }
+        // Walk down the tree for the last line whose top is at or above `offset`.
+        let mut remaining = offset.0;
+        let mut position = 0;
+        let mut step = self.tree.len().next_power_of_two();
+        while step > 0 {
+            let next = position + step;
+            if next < self.tree.len() && self.tree[next] <= remaining {
+                position = next;
+                remaining -= self.tree[next];
+            }
+            step /= 2;
+        }
+        position.min(self.heights.len() - 1)
+    }
+
+    fn rebuild(&mut self) {
+        self.tree = vec![0; self.heights.len() + 1];
+        for (line, height) in self.heights.iter().enumerate() {
+            let i = line + 1;
+            self.tree[i] += height.0;
+            let parent = i + (i & i.wrapping_neg());
+            if parent < self.tree.len() {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+// Lines are hidden as lists of ranges, which may hold just one.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use crate::buffer::history::EditKind;
+    use crate::editor::Selection;
+
+    use super::*;
+
+    const HEIGHT: Au = Au(10);
+
+    fn edit(buffer: &mut Buffer, range: Range<usize>, text: &str) {
+        let selection = Selection::caret(range.start);
+        buffer.edit(range, text, EditKind::Other, selection, selection);
+    }
+
+    fn heights(line_index: &LineIndex) -> Vec<i32> {
+        line_index.heights.iter().map(|height| height.0).collect()
+    }
+
+    #[test]
+    fn heights_move_with_the_lines_around_edits() {
+        let mut buffer = Buffer::from("a\nb\nc\nd\n");
+        let mut line_index = LineIndex::default();
+        line_index.update(&buffer, HEIGHT);
+        line_index.set_height(2, Au(30));
+        line_index.set_hidden(vec![3..4], HEIGHT);
+        assert_eq!(heights(&line_index), [10, 10, 30, 0, 10]);
+
+        // Two lines added above the wrapped and folded ones.
+        edit(&mut buffer, 0..0, "x\ny\n");
+        line_index.update(&buffer, HEIGHT);
+        assert_eq!(heights(&line_index), [10, 10, 10, 10, 30, 0, 10]);
+        assert!(line_index.is_hidden(5) && !line_index.is_hidden(3));
+        assert_eq!(line_index.total(), Au(80));
+
+        // One line removed above them again.
+        let lines = buffer.line_to_char(1)..buffer.line_to_char(2);
+        edit(&mut buffer, lines, "");
+        line_index.update(&buffer, HEIGHT);
+        assert_eq!(heights(&line_index), [10, 10, 10, 30, 0, 10]);
+        assert_eq!(line_index.offset(4), Au(60));
+        assert_eq!(line_index.line_at(Au(35)), 3);
+    }
+
+    #[test]
+    fn folds_lose_the_lines_removed_from_them() {
+        let mut buffer = Buffer::from("a\nb\nc\nd\ne\n");
+        let mut line_index = LineIndex::default();
+        line_index.update(&buffer, HEIGHT);
+        line_index.set_hidden(vec![1..4], HEIGHT);
+
+        let lines = buffer.line_to_char(2)..buffer.line_to_char(3);
+        edit(&mut buffer, lines, "");
+        line_index.update(&buffer, HEIGHT);
+        assert_eq!(heights(&line_index), [10, 0, 0, 10, 10]);
+
+        // Showing the lines again gives them back their height.
+        line_index.set_hidden(Vec::new(), HEIGHT);
+        assert_eq!(line_index.total(), Au(50));
+    }
+
+    #[test]
+    fn reset_keeps_folded_lines_hidden() {
+        let buffer = Buffer::from("a\nb\nc");
+        let mut line_index = LineIndex::default();
+        line_index.update(&buffer, HEIGHT);
+        line_index.set_hidden(vec![1..2], HEIGHT);
+        line_index.reset(Au(20));
+        assert_eq!(heights(&line_index), [20, 0, 20]);
+    }
+}
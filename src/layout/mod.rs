@@ -4,10 +4,17 @@ use app_units::Au;
 use euclid::num::Zero;
 use webrender_api::units::{LayoutRect, LayoutPoint, LayoutSize};
 
+pub mod caret;
 pub mod context;
 pub mod display_list;
+pub mod font;
 pub mod fragment;
+pub mod gutter;
+pub mod hit_test;
 pub mod inline;
+pub mod line_break;
+pub mod line_index;
+pub mod span_layer;
 pub mod widget;
 
 #[derive(Clone, Copy, Default)]
@@ -0,0 +1,154 @@
+use std::ops::Range;
+
+use crate::buffer::edits::Edit;
+use crate::buffer::{Buffer, Snapshot};
+
+use super::widget::{Span, SpanStyle};
+
+/// Styled spans set on a text from outside, such as diagnostics, that stay on
+/// the text they were set on as the buffer is edited around them.
+#[derive(Default)]
+pub struct SpanLayer {
+    /// Byte ranges of the buffer and their styles, sorted and not overlapping.
+    spans: Vec<(Range<usize>, SpanStyle)>,
+    /// Revision of the buffer the ranges are in.
+    revision: u64,
+}
+
+impl SpanLayer {
+    /// Replace the spans with `spans`, whose char ranges are in `buffer` as it
+    /// is now. They must be sorted and not overlap.
+    pub fn set(&mut self, buffer: &Buffer, spans: Vec<Span>) {
+        self.spans = spans
+            .into_iter()
+            .map(|span| {
+                let range = buffer.char_to_byte(span.range.start)..buffer.char_to_byte(span.range.end);
+                (range, span.style)
+            })
+            .collect();
+        self.revision = buffer.revision();
+    }
+
+    /// Catch up with the edits made to `buffer`. Spans move with the text
+    /// around them and lose the text removed from them; text inserted inside
+    /// a span takes its style, and text inserted at its edges does not.
+    pub fn update(&mut self, buffer: &Buffer) {
+        if self.revision == buffer.revision() {
+            return;
+        }
+        match buffer.edits_since(self.revision) {
+            Some(edits) => {
+                for edit in edits {
+                    self.shift(edit);
+                }
+            }
+            // Too far behind to tell where the spans went.
+            None => self.spans.clear(),
+        }
+        self.revision = buffer.revision();
+    }
+
+    fn shift(&mut self, edit: &Edit) {
+        let moved = |position: usize| position - edit.old_end_byte + edit.new_end_byte;
+        for (range, _) in &mut self.spans {
+            let start = if range.start >= edit.old_end_byte {
+                moved(range.start)
+            } else if range.start <= edit.start_byte {
+                range.start
+            } else {
+                edit.new_end_byte
+            };
+            let end = if range.end <= edit.start_byte {
+                range.end
+            } else if range.end >= edit.old_end_byte {
+                moved(range.end)
+            } else {
+                edit.start_byte
+            };
+            *range = start..end;
+        }
+        self.spans.retain(|(range, _)| range.start < range.end);
+    }
+
+    /// The spans on a line of `snapshot`, which must be the contents of the
+    /// buffer as of the last update, with their char ranges.
+    pub fn line_spans(&self, snapshot: &Snapshot, line_idx: usize) -> Vec<Span> {
+        let line_start = snapshot.line_to_byte(line_idx);
+        let line_end = if line_idx + 1 < snapshot.len_lines() {
+            snapshot.line_to_byte(line_idx + 1)
+        } else {
+            snapshot.char_to_byte(snapshot.len_chars())
+        };
+        let first = self.spans.partition_point(|(range, _)| range.end <= line_start);
+        self.spans[first..]
+            .iter()
+            .take_while(|(range, _)| range.start < line_end)
+            .map(|(range, style)| Span {
+                range: snapshot.byte_to_char(range.start)..snapshot.byte_to_char(range.end),
+                style: style.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use webrender_api::ColorF;
+
+    use crate::buffer::history::EditKind;
+    use crate::editor::Selection;
+
+    use super::*;
+
+    fn edit(buffer: &mut Buffer, range: Range<usize>, text: &str) {
+        let selection = Selection::caret(range.start);
+        buffer.edit(range, text, EditKind::Other, selection, selection);
+    }
+
+    fn style() -> SpanStyle {
+        SpanStyle { color: Some(ColorF::new(1.0, 0.0, 0.0, 1.0)), ..SpanStyle::default() }
+    }
+
+    /// The text of each span on each line.
+    fn spanned(layer: &mut SpanLayer, buffer: &Buffer) -> Vec<String> {
+        layer.update(buffer);
+        let snapshot = buffer.snapshot();
+        (0..snapshot.len_lines())
+            .flat_map(|line_idx| layer.line_spans(&snapshot, line_idx))
+            .map(|span| snapshot.slice(span.range).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn spans_survive_inserts_before_them() {
+        let mut buffer = Buffer::from("let x = y;\nlet z;");
+        let mut layer = SpanLayer::default();
+        layer.set(&buffer, vec![Span { range: 8..9, style: style() }, Span { range: 15..16, style: style() }]);
+        assert_eq!(spanned(&mut layer, &buffer), ["y", "z"]);
+
+        edit(&mut buffer, 0..0, "// \u{e9}t\u{e9}\n");
+        edit(&mut buffer, 11..11, "mut ");
+        assert_eq!(buffer.to_string(), "// \u{e9}t\u{e9}\nlet mut x = y;\nlet z;");
+        assert_eq!(spanned(&mut layer, &buffer), ["y", "z"]);
+    }
+
+    #[test]
+    fn spans_grow_and_shrink_with_edits_inside_them() {
+        let mut buffer = Buffer::from("abc defg hij");
+        let mut layer = SpanLayer::default();
+        layer.set(&buffer, vec![Span { range: 4..8, style: style() }]);
+
+        // Text typed at the edges stays outside.
+        edit(&mut buffer, 4..4, "(");
+        edit(&mut buffer, 9..9, ")");
+        edit(&mut buffer, 7..7, "\u{2192}");
+        assert_eq!(spanned(&mut layer, &buffer), ["de\u{2192}fg"]);
+
+        // Removing part of a span clips it, and removing all of it drops it.
+        edit(&mut buffer, 3..7, "");
+        assert_eq!(spanned(&mut layer, &buffer), ["\u{2192}fg"]);
+        edit(&mut buffer, 2..7, "");
+        assert_eq!(buffer.to_string(), "ab hij");
+        assert!(spanned(&mut layer, &buffer).is_empty());
+    }
+}
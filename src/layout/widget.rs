@@ -1,173 +1,792 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::iter::Peekable;
+use std::ops::Range;
+use std::rc::Rc;
+use std::slice::Iter;
 
 use app_units::Au;
-use ttf_parser::{Weight, Style as FontStyle};
-use webrender_api::{FontInstanceKey, FontKey};
+use rustybuzz::Feature;
+use ttf_parser::{Style as FontStyle, Tag, Weight};
+use unicode_bidi::{Level, ParagraphBidiInfo};
+use unicode_script::UnicodeScript;
+use unicode_segmentation::UnicodeSegmentation;
+use webrender_api::ColorF;
+
+use crate::buffer::{Buffer, Snapshot};
+use crate::buffer::wrap::Wrap;
+use crate::highlight::Highlight;
+use crate::theme::Theme;
 
 use super::context::LayoutContext;
 use super::{Rect, Size, Sides};
-use super::fragment::{TextFragment, BoxFragment, Fragment};
-use super::inline::{FaceInfo, TextRun, InlineState};
+use super::fragment::{TextFragment, BoxFragment, BoxKind, Fragment};
+use super::display_list::DisplayListBuilder;
+use super::gutter::{LineNumbers, Marker, MarkerLane};
+use super::inline::{column_width, visual_order, FaceInfo, FontMetrics, GlyphInfo, TextRun, InlineState};
+use super::line_break::LineBreaks;
+use super::line_index::LineIndex;
+use super::span_layer::SpanLayer;
+
+/// A change to the editor that widgets showing it may have to follow.
+pub enum WidgetEvent<'a> {
+    Theme(&'a Theme),
+    /// Text is scaled by a new factor.
+    Zoom(f32),
+    /// The caret moved to a char of `buffer`, and out of every other buffer.
+    Caret {
+        buffer: &'a Rc<RefCell<Buffer>>,
+        position: usize,
+    },
+    /// Lines of `buffer` were folded away or shown again.
+    HiddenLines {
+        buffer: &'a Rc<RefCell<Buffer>>,
+        lines: &'a [Range<usize>],
+    },
+    /// The wrap settings of `buffer` changed.
+    Wrap(&'a Rc<RefCell<Buffer>>),
+    LineNumbers(LineNumbers),
+    /// The markers in a lane of the gutters beside `buffer` were replaced.
+    Markers {
+        buffer: &'a Rc<RefCell<Buffer>>,
+        lane: MarkerLane,
+        markers: &'a [Marker],
+    },
+}
 
+/// A part of the window that lays itself out as a box, paints that box, and
+/// follows changes to the editor. Blocks compose widgets into columns and
+/// rows, and are widgets themselves.
 pub trait Widget {
+    /// The width the widget needs beside others in a row, or `None` if it
+    /// takes a share of the width they leave.
+    fn measure(&self, context: &mut LayoutContext) -> Option<Au>;
+
+    /// Lay out the widget as a box at the top left of `containing_block`,
+    /// only shaping text within `visible`, which is relative to the box.
+    fn layout(&self, context: &mut LayoutContext, containing_block: Size<Au>, visible: Range<Au>) -> BoxFragment;
+
+    /// Paint `fragment`, which the last layout of the widget made.
+    fn paint(&self, fragment: &BoxFragment, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+        fragment.build_display_list(builder, containing_block);
+    }
+
+    fn handle_event(&mut self, event: &WidgetEvent);
+
+    /// Height of the widget as of its last layout.
+    fn height(&self) -> Au;
 
+    /// Offset of the top of a buffer line from the top of the widget, if the
+    /// widget shows `buffer`.
+    fn line_offset(&self, _buffer: &Rc<RefCell<Buffer>>, _line_idx: usize) -> Option<Au> {
+        None
+    }
 }
 
+#[derive(Clone)]
 pub struct TextStyle {
-    pub color: u32,
+    pub color: ColorF,
     pub font_family: String,
     pub font_size: f32,
     pub font_weight: Weight,
     pub font_style: FontStyle,
     pub line_height: f32,
+    /// OpenType features such as `liga=0`, `ss01` or `zero`, for the whole text.
+    pub font_features: Vec<Feature>,
 }
 
 impl Default for TextStyle {
     fn default() -> Self {
         Self {
-            color: 0,
+            color: ColorF::BLACK,
             font_family: "".to_string(),
             font_size: 16.0,
             font_weight: Weight::Normal,
             font_style: FontStyle::Normal,
-            line_height: 22.0
+            line_height: 22.0,
+            font_features: Vec::new(),
         }
     }
 }
 
+/// Style for a span of text, overriding its `TextStyle` where set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpanStyle {
+    pub color: Option<ColorF>,
+    pub font_weight: Option<Weight>,
+    pub font_style: Option<FontStyle>,
+}
+
+/// A styled range of buffer chars.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub style: SpanStyle,
+}
+
 pub struct Text {
-    pub text: String,
+    pub buffer: Rc<RefCell<Buffer>>,
     pub style: TextStyle,
+    /// Heights of the buffer's lines as last laid out, and which are folded
+    /// away, so that only the visible ones need shaping. Gutters beside the
+    /// text share it.
+    line_index: Rc<RefCell<LineIndex>>,
+    /// Where the caret is, if this text has one.
+    caret: Cell<Option<usize>>,
+    /// Styled spans set explicitly, e.g. for diagnostics, over the syntax.
+    spans: RefCell<SpanLayer>,
+    /// Factor to scale the sizes of the style by.
+    zoom: Cell<f32>,
+    /// Styles the text by its syntax, under the spans set explicitly.
+    highlighter: RefCell<Option<Box<dyn Highlight>>>,
 }
 
+impl Text {
+    pub fn new(buffer: Rc<RefCell<Buffer>>, style: TextStyle) -> Text {
+        Text {
+            buffer,
+            style,
+            line_index: Rc::new(RefCell::new(LineIndex::default())),
+            caret: Cell::new(None),
+            spans: RefCell::new(SpanLayer::default()),
+            zoom: Cell::new(1.0),
+            highlighter: RefCell::new(None),
+        }
+    }
 
-pub struct FontCache {
-    font_keys: HashMap<FaceInfo, FontKey>,
-    font_instance_keys: HashMap<(FaceInfo, u8), FontInstanceKey>,
-    fonts: HashMap<FaceInfo, Vec<u8>>,
-}
+    /// The heights of the lines, for numbering them beside the text.
+    pub(super) fn line_index(&self) -> Rc<RefCell<LineIndex>> {
+        self.line_index.clone()
+    }
 
-impl FontCache {
-    pub fn new() -> FontCache {
-        FontCache {
-            font_keys: HashMap::new(),
-            font_instance_keys: HashMap::new(),
-            fonts: HashMap::new()
+    /// Style the text by its syntax with `highlighter`.
+    pub fn set_highlighter(&self, highlighter: Box<dyn Highlight>) {
+        *self.highlighter.borrow_mut() = Some(highlighter);
+    }
+
+    /// Color the text and its syntax with `theme`.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        self.style.color = theme.foreground;
+        if let Some(highlighter) = self.highlighter.get_mut() {
+            highlighter.set_theme(theme);
         }
     }
 
-    pub fn get_font(&mut self, face_info: &FaceInfo) -> &[u8] {
-        self.fonts.entry(face_info.clone()).or_insert_with(||std::fs::read(&face_info.path).unwrap())
+    /// Scale the font size and line height of the style by `zoom`.
+    pub fn set_zoom(&self, zoom: f32) {
+        if zoom == self.zoom.get() {
+            return;
+        }
+        self.zoom.set(zoom);
+        // Lines laid out at the old size would be placed wrongly.
+        self.line_index.borrow_mut().reset(Au::from_f32_px(self.line_height()));
     }
 
-    pub fn get_font_instance_key(&mut self, face_info: &FaceInfo, font_size: u8) -> Option<FontInstanceKey>{
-        self.font_instance_keys.get(&(face_info.clone(), font_size)).map(|k| k.to_owned())
+    fn font_size(&self) -> f32 {
+        self.style.font_size * self.zoom.get()
     }
-}
 
+    fn line_height(&self) -> f32 {
+        self.style.line_height * self.zoom.get()
+    }
 
-impl Text {
-    pub fn layout(&self, inline_state: &mut InlineState, context: &mut LayoutContext) -> Vec<TextFragment> {
-        let line_height = Au::from_f32_px(self.style.line_height);
-        let text_runs = self.itemize();
+    /// Replace the styled spans, whose char ranges are in the buffer as it is
+    /// now. They must be sorted and not overlap; text outside of them has the
+    /// style of its syntax. Edits move them along with their text.
+    pub fn set_spans(&self, spans: Vec<Span>) {
+        self.spans.borrow_mut().set(&self.buffer.borrow(), spans);
+    }
+
+    /// Hide the lines in `hidden`, e.g. because they are folded, showing any
+    /// that were hidden before and are not in it.
+    pub fn set_hidden_lines(&self, hidden: Vec<Range<usize>>) {
+        let line_height = Au::from_f32_px(self.line_height());
+        let mut line_index = self.line_index.borrow_mut();
+        line_index.update(&self.buffer.borrow(), line_height);
+        // Lines shown again are assumed not to wrap until they are laid out.
+        line_index.set_hidden(hidden, line_height);
+    }
+
+    /// Move the caret, which affects how the text around it is shaped.
+    pub fn set_caret(&self, position: Option<usize>) {
+        self.caret.set(position);
+    }
+
+    /// Offset of the top of a buffer line from the top of the text. Lines that
+    /// have not been laid out are assumed not to wrap.
+    pub fn line_offset(&self, line_idx: usize) -> Au {
+        let mut line_index = self.line_index.borrow_mut();
+        line_index.update(&self.buffer.borrow(), Au::from_f32_px(self.line_height()));
+        line_index.offset(line_idx)
+    }
+
+    /// Lay out the lines that intersect `inline_state.visible`, and advance
+    /// the block position past the whole text.
+    pub fn layout_lines(&self, inline_state: &mut InlineState, context: &mut LayoutContext) -> Vec<TextFragment> {
+        let line_height = Au::from_f32_px(self.line_height());
+        let snapshot = self.buffer.borrow().snapshot();
+        let mut line_index = self.line_index.borrow_mut();
+        line_index.update(&self.buffer.borrow(), line_height);
+        self.spans.borrow_mut().update(&self.buffer.borrow());
+        let mut highlighter = self.highlighter.borrow_mut();
+        if let Some(highlighter) = highlighter.as_mut() {
+            highlighter.update(&self.buffer.borrow());
+        }
         let mut fragments = Vec::new();
 
-        let mut cur_b = Au(0);
+        let settings = self.buffer.borrow().wrap();
+        let column_width = self.column_width(context);
+        let indent = column_width * settings.continuation_indent as i32;
+        let wrap_width = match settings.wrap {
+            Wrap::None => None,
+            Wrap::Window => Some(inline_state.containing_block.width),
+            Wrap::Column(columns) => Some(column_width * columns as i32),
+        };
+
+        let top = inline_state.block_position;
+        let first_line = line_index.line_at(inline_state.visible.start - top);
+        let mut cur_b = top + line_index.offset(first_line);
+
+        for line_idx in first_line..snapshot.len_lines() {
+            if cur_b >= inline_state.visible.end {
+                break;
+            }
+            if line_index.is_hidden(line_idx) {
+                line_index.set_height(line_idx, Au(0));
+                continue;
+            }
+            let line_top = cur_b;
+            let text = snapshot.line_text(line_idx);
+            let syntax_spans = highlighter
+                .as_mut()
+                .map(|highlighter| highlighter.line_spans(&snapshot, line_idx))
+                .unwrap_or_default();
+            let runs = self.itemize(&snapshot, line_idx, &syntax_spans, context);
+            // Line breaking needs the glyphs in logical order, but right-to-left
+            // runs are shaped in visual order.
+            let shaped: Vec<Vec<GlyphInfo>> = runs
+                .iter()
+                .map(|run| {
+                    let mut glyphs = run.shape(context);
+                    if run.rtl {
+                        glyphs.reverse();
+                    }
+                    glyphs
+                })
+                .collect();
+            let line_starts = match wrap_width {
+                Some(width) => LineBreaks::new(&text, snapshot.line_to_char(line_idx))
+                    .wrap(&shaped.concat(), width, indent),
+                None => Vec::new(),
+            };
 
-        for run in text_runs {
-            let mut infos = run.shape(context);
-            let mut remains = Vec::new();
+            // Split the runs between visual lines, keeping logical order.
+            let mut rows: Vec<Vec<(&TextRun, Vec<GlyphInfo>, Range<usize>)>> =
+                (0..=line_starts.len()).map(|_| Vec::new()).collect();
+            let mut row = 0;
+            for (run, mut glyphs) in runs.iter().zip(shaped) {
+                let run_end = run.offset + run.text.chars().count();
+                let mut start = run.offset;
 
-            let mut len = Au(0);
+                loop {
+                    while row < line_starts.len() && start >= line_starts[row] {
+                        row += 1;
+                    }
+                    let row_end = line_starts.get(row).copied().unwrap_or(run_end);
+                    let split = glyphs.iter().position(|glyph| glyph.cluster >= row_end).unwrap_or(glyphs.len());
+                    let remains = glyphs.split_off(split);
+                    let end = row_end.min(run_end);
+                    rows[row].push((run, glyphs, start..end));
 
-            loop {
-                for i in 0..infos.len() {
-                    let info = infos[i];
-                    if len + info.advance > inline_state.containing_block.width {
-                        remains = infos.split_off(i);
+                    if remains.is_empty() {
                         break;
-                    } else {
-                        len += info.advance;
                     }
+                    glyphs = remains;
+                    start = end;
                 }
+            }
 
-                let rect = Rect {
-                    origin: super::Point { i: Au(0), b: cur_b },
-                    size: Size { width: len, height: line_height }
-                };
-                let fragment = TextFragment {
-                    font_size: run.font_size,
-                    glyphs: infos,
-                    rect: rect,
-                    face_info: run.face_info.clone(),
-                };
-                fragments.push(fragment);
-
-                if remains.len() == 0 {
-                    break;
+            for (row, pieces) in rows.into_iter().enumerate() {
+                if row > 0 {
+                    cur_b += line_height;
+                }
+                let levels: Vec<Level> = pieces.iter().map(|(run, ..)| run.level).collect();
+                let metrics: Vec<FontMetrics> = pieces.iter().map(|(run, ..)| run.metrics(context)).collect();
+                // Runs in different fonts share the lowest of their baselines.
+                let baseline = metrics
+                    .iter()
+                    .map(|metrics| metrics.baseline(line_height))
+                    .max()
+                    .unwrap_or(Au(0));
+                let mut pieces: Vec<_> = pieces.into_iter().map(Some).collect();
+                let mut cur_i = if row > 0 { indent } else { Au(0) };
+                for index in visual_order(&levels) {
+                    let Some((run, mut glyphs, range)) = pieces[index].take() else {
+                        continue;
+                    };
+                    if run.rtl {
+                        glyphs.reverse();
+                    }
+                    let width = glyphs.iter().fold(Au(0), |width, glyph| width + glyph.advance);
+                    let text = run.text.chars().skip(range.start - run.offset).take(range.len()).collect();
+                    let rect = Rect {
+                        origin: super::Point { i: cur_i, b: cur_b },
+                        size: Size { width, height: line_height }
+                    };
+                    let fragment = TextFragment {
+                        font_size: run.font_size,
+                        glyphs,
+                        range,
+                        text,
+                        rtl: run.rtl,
+                        color: run.color,
+                        metrics: metrics[index],
+                        baseline,
+                        rect: rect,
+                        face_info: run.face_info.clone(),
+                    };
+                    fragments.push(fragment);
+                    cur_i += width;
                 }
-                infos = remains;
-                remains = Vec::new();
-                len = Au(0);
-                cur_b += line_height;
             }
+            cur_b += line_height;
+            line_index.set_height(line_idx, cur_b - line_top);
         }
 
+        inline_state.block_position = top + line_index.total();
         fragments
     }
 
-    pub fn itemize(&self) -> Vec<TextRun> {
-        let mut runs = Vec::new();
-        let run = TextRun {
-            text: self.text.clone(),
-            face_info: FaceInfo { path: "resources/FiraCode-Regular.otf".into(), index: 0 },
-            font_size: self.style.font_size,
-            script: rustybuzz::script::LATIN,
-            rtl: false
-        };
-        runs.push(run);
+    fn column_width(&self, context: &mut LayoutContext) -> Au {
+        let face_info = self.face_info(context);
+        column_width(context, &face_info, self.font_size())
+    }
+
+    /// The face the style asks for.
+    fn face_info(&self, context: &mut LayoutContext) -> FaceInfo {
+        context.resolve_font(&self.style.font_family, self.style.font_weight, self.style.font_style)
+    }
+
+    /// Split a line of the buffer into runs that can each be shaped and
+    /// painted on their own. Each grapheme takes the style of the span it is
+    /// in, over that of the syntax span it is in, and the face of that style
+    /// if it has glyphs for it, otherwise a fallback from the installed fonts.
+    pub fn itemize(
+        &self,
+        snapshot: &Snapshot,
+        line_idx: usize,
+        syntax_spans: &[Span],
+        context: &mut LayoutContext,
+    ) -> Vec<TextRun> {
+        let text = snapshot.line_text(line_idx);
+
+        // Each line is a paragraph of its own for the bidi algorithm.
+        let bidi = ParagraphBidiInfo::new(&text, None);
+        // Chars common to all scripts, like punctuation and digits, take the
+        // script of the text before them, or at the start of a line after them.
+        let mut script = text
+            .chars()
+            .map(|c| c.script())
+            .find(|&script| is_real_script(script))
+            .map_or(rustybuzz::script::LATIN, to_shaping_script);
+
+        let line_start = snapshot.line_to_char(line_idx);
+        let no_ligatures = self.caret.get().and_then(|caret| {
+            let token = caret_token(&text, caret.checked_sub(line_start)?)?;
+            Some(line_start + token.start..line_start + token.end)
+        });
+
+        let spans = self.spans.borrow().line_spans(snapshot, line_idx);
+        let mut spans = spans.iter().peekable();
+        let mut syntax_spans = syntax_spans.iter().peekable();
+
+        let mut runs: Vec<TextRun> = Vec::new();
+        let mut offset = line_start;
+        for (byte, grapheme) in text.grapheme_indices(true) {
+            let span_style = span_style_at(&mut spans, offset);
+            let syntax_style = span_style_at(&mut syntax_spans, offset);
+            let weight = span_style
+                .and_then(|style| style.font_weight)
+                .or(syntax_style.and_then(|style| style.font_weight))
+                .unwrap_or(self.style.font_weight);
+            let font_style = span_style
+                .and_then(|style| style.font_style)
+                .or(syntax_style.and_then(|style| style.font_style))
+                .unwrap_or(self.style.font_style);
+            let color = span_style
+                .and_then(|style| style.color)
+                .or(syntax_style.and_then(|style| style.color))
+                .unwrap_or(self.style.color);
+
+            let primary = context.resolve_font(&self.style.font_family, weight, font_style);
+            let face_info = if context.covers(&primary, grapheme) {
+                primary
+            } else {
+                context.fallback_font(grapheme, weight, font_style).unwrap_or(primary)
+            };
+            if let Some(c) = grapheme.chars().next() && is_real_script(c.script()) {
+                script = to_shaping_script(c.script());
+            }
+            let level = bidi.levels[byte];
+            match runs.last_mut() {
+                Some(run) if run.face_info == face_info &&
+                    run.script == script &&
+                    run.level == level &&
+                    run.color == color => {
+                    run.text.push_str(grapheme);
+                }
+                _ => runs.push(TextRun {
+                    text: grapheme.to_owned(),
+                    offset,
+                    face_info,
+                    font_size: self.font_size(),
+                    color,
+                    script,
+                    features: Vec::new(),
+                    level,
+                    rtl: level.is_rtl(),
+                }),
+            }
+            offset += grapheme.chars().count();
+        }
+
+        for run in &mut runs {
+            run.features = self.style.font_features.clone();
+            // Show the chars of the token being edited as they are typed,
+            // rather than as a ligature of the whole token.
+            if let Some(token) = &no_ligatures {
+                let start = token.start.max(run.offset) - run.offset;
+                let end = token.end.min(run.offset + run.text.chars().count()).saturating_sub(run.offset);
+                if start < end {
+                    let to_byte = |char_idx| {
+                        run.text.char_indices().nth(char_idx).map_or(run.text.len(), |(byte, _)| byte)
+                    };
+                    let range = to_byte(start)..to_byte(end);
+                    for tag in [b"liga", b"calt", b"dlig"] {
+                        run.features.push(Feature::new(Tag::from_bytes(tag), 0, range.clone()));
+                    }
+                }
+            }
+        }
+
+        // An empty line still needs a run, for the caret to sit in.
+        if runs.is_empty() {
+            runs.push(TextRun {
+                text: String::new(),
+                offset,
+                face_info: self.face_info(context),
+                font_size: self.font_size(),
+                color: self.style.color,
+                script,
+                features: self.style.font_features.clone(),
+                level: Level::ltr(),
+                rtl: false
+            });
+        }
         runs
     }
 }
 
-pub struct Block {
-    children: Vec<Text>
+/// The style of the span containing `offset`, skipping spans that end before
+/// it. Offsets must be asked for in increasing order.
+fn span_style_at<'a>(spans: &mut Peekable<Iter<'a, Span>>, offset: usize) -> Option<&'a SpanStyle> {
+    while spans.next_if(|span| span.range.end <= offset).is_some() {}
+    spans
+        .peek()
+        .filter(|span| span.range.start <= offset)
+        .map(|span| &span.style)
 }
 
-impl Block {
-    pub fn new(children: Vec<Text>) -> Block {
-        Block {
-            children
+/// The token of `text` around the char index `caret`: the run of word chars
+/// or of punctuation touching it on either side.
+fn caret_token(text: &str, caret: usize) -> Option<Range<usize>> {
+    let chars: Vec<char> = text.chars().collect();
+    if caret > chars.len() {
+        return None;
+    }
+    let class = |c: char| {
+        if c.is_whitespace() {
+            None
+        } else {
+            Some(c.is_alphanumeric() || c == '_')
+        }
+    };
+    let mut start = caret;
+    if let Some(before) = caret.checked_sub(1).and_then(|i| class(chars[i])) {
+        while start > 0 && class(chars[start - 1]) == Some(before) {
+            start -= 1;
         }
     }
+    let mut end = caret;
+    if let Some(after) = chars.get(caret).and_then(|&c| class(c)) {
+        while end < chars.len() && class(chars[end]) == Some(after) {
+            end += 1;
+        }
+    }
+    (start < end).then_some(start..end)
+}
+
+/// Whether chars of `script` decide the script of a run, unlike those shared
+/// between scripts or inheriting the script of the char before.
+fn is_real_script(script: unicode_script::Script) -> bool {
+    !matches!(
+        script,
+        unicode_script::Script::Common | unicode_script::Script::Inherited | unicode_script::Script::Unknown
+    )
+}
+
+fn to_shaping_script(script: unicode_script::Script) -> rustybuzz::Script {
+    let tag = Tag::from_bytes_lossy(script.short_name().as_bytes());
+    rustybuzz::Script::from_iso15924_tag(tag).unwrap_or(rustybuzz::script::LATIN)
+}
+
+impl Widget for Text {
+    fn measure(&self, _context: &mut LayoutContext) -> Option<Au> {
+        None
+    }
 
-    pub fn layout(&self, context: &mut LayoutContext, containing_block: Size<Au>) -> BoxFragment {
+    fn layout(&self, context: &mut LayoutContext, containing_block: Size<Au>, visible: Range<Au>) -> BoxFragment {
         let mut inline_state = InlineState {
             lines: Vec::new(),
             inline_position: Au(0),
+            block_position: Au(0),
             containing_block,
+            visible,
         };
+        let mut fragment = empty_box(containing_block);
+        fragment.children = self.layout_lines(&mut inline_state, context).into_iter().map(Fragment::Text).collect();
+        fragment.rect.size.height = inline_state.block_position;
+        fit_children(&mut fragment);
+        fragment
+    }
 
-        let mut fragment = BoxFragment {
-            rect: Rect {
-                origin: super::Point { i: Au(0), b: Au(0) },
-                size: containing_block,
-            },
-            margin: Sides::<Au>::zero(),
-            children: Vec::new(),
-        };
+    fn handle_event(&mut self, event: &WidgetEvent) {
+        match *event {
+            WidgetEvent::Theme(theme) => self.set_theme(theme),
+            WidgetEvent::Zoom(zoom) => self.set_zoom(zoom),
+            WidgetEvent::Caret { buffer, position } => {
+                self.set_caret(Rc::ptr_eq(&self.buffer, buffer).then_some(position));
+            }
+            WidgetEvent::HiddenLines { buffer, lines } if Rc::ptr_eq(&self.buffer, buffer) => {
+                self.set_hidden_lines(lines.to_vec());
+            }
+            // Lines wrapped at the old settings would be placed wrongly.
+            WidgetEvent::Wrap(buffer) if Rc::ptr_eq(&self.buffer, buffer) => {
+                self.line_index.borrow_mut().reset(Au::from_f32_px(self.line_height()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Height of the whole text, as of the last layout.
+    fn height(&self) -> Au {
+        self.line_index.borrow().total()
+    }
 
+    fn line_offset(&self, buffer: &Rc<RefCell<Buffer>>, line_idx: usize) -> Option<Au> {
+        Rc::ptr_eq(&self.buffer, buffer).then(|| Text::line_offset(self, line_idx))
+    }
+}
+
+/// How a block places its children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// One below the other, each as wide as the block.
+    Column,
+    /// Side by side, each as tall as its content.
+    Row,
+}
 
-        for text in &self.children {
-            let fragments = text.layout(&mut inline_state, context);
-            for frag in fragments {
-                fragment.children.push(Fragment::Text(frag));
+pub struct Block {
+    direction: Direction,
+    children: Vec<Box<dyn Widget>>,
+}
+
+impl Block {
+    pub fn new(direction: Direction, children: Vec<Box<dyn Widget>>) -> Block {
+        Block {
+            direction,
+            children
+        }
+    }
+
+    fn layout_column(&self, context: &mut LayoutContext, containing_block: Size<Au>, visible: Range<Au>) -> BoxFragment {
+        let mut fragment = empty_box(containing_block);
+        let mut top = Au(0);
+        for child in &self.children {
+            let mut child_fragment = child.layout(context, containing_block, visible.start - top..visible.end - top);
+            child_fragment.rect.origin.b = top;
+            top += child_fragment.rect.size.height;
+            fragment.children.push(Fragment::Box(child_fragment));
+        }
+        fragment.rect.size.height = top;
+        fragment
+    }
+
+    /// Children that measure their width get it, and the others share the
+    /// rest. Each child is as tall as its content.
+    fn layout_row(&self, context: &mut LayoutContext, containing_block: Size<Au>, visible: Range<Au>) -> BoxFragment {
+        let widths: Vec<Option<Au>> = self.children.iter().map(|child| child.measure(context)).collect();
+        let fixed = widths.iter().flatten().fold(Au(0), |sum, &width| sum + width);
+        let shared = widths.iter().filter(|width| width.is_none()).count().max(1) as i32;
+        let shared_width = ((containing_block.width - fixed) / shared).max(Au(0));
+
+        // Widgets of a fixed width, like gutters, describe the text beside
+        // them as it was just laid out, so the others go first.
+        let mut boxes: Vec<Option<BoxFragment>> = self.children.iter().map(|_| None).collect();
+        for is_fixed in [false, true] {
+            for ((child, width), slot) in self.children.iter().zip(&widths).zip(&mut boxes) {
+                if width.is_some() == is_fixed {
+                    let size = Size { width: width.unwrap_or(shared_width), height: containing_block.height };
+                    *slot = Some(child.layout(context, size, visible.clone()));
+                }
             }
         }
+
+        let mut fragment = empty_box(containing_block);
+        fragment.rect.size.height = Au(0);
+        let mut inline_position = Au(0);
+        for (mut region, width) in boxes.into_iter().flatten().zip(widths) {
+            region.rect.origin.i = inline_position;
+            inline_position += width.unwrap_or(shared_width);
+            fragment.rect.size.height = fragment.rect.size.height.max(region.rect.size.height);
+            fragment.children.push(Fragment::Box(region));
+        }
+        fragment
+    }
+}
+
+impl Widget for Block {
+    /// A row of widgets that all measure their width is as wide as all of
+    /// them, and a column as wide as the widest.
+    fn measure(&self, context: &mut LayoutContext) -> Option<Au> {
+        let mut width = Au(0);
+        for child in &self.children {
+            let child_width = child.measure(context)?;
+            width = match self.direction {
+                Direction::Column => width.max(child_width),
+                Direction::Row => width + child_width,
+            };
+        }
+        Some(width)
+    }
+
+    /// Lay out the children, only shaping text within `visible`. The
+    /// fragment is as tall as its content, and as wide as the widest line laid
+    /// out if that is wider than the containing block.
+    fn layout(&self, context: &mut LayoutContext, containing_block: Size<Au>, visible: Range<Au>) -> BoxFragment {
+        let mut fragment = match self.direction {
+            Direction::Column => self.layout_column(context, containing_block, visible),
+            Direction::Row => self.layout_row(context, containing_block, visible),
+        };
+        fit_children(&mut fragment);
         fragment
     }
+
+    /// Gutters are painted after the other children, over the text that
+    /// scrolls sideways under them.
+    fn paint(&self, fragment: &BoxFragment, builder: &mut DisplayListBuilder, containing_block: Rect<Au>) {
+        let containing_block = Rect {
+            origin: containing_block.origin + fragment.rect.origin,
+            size: fragment.rect.size,
+        };
+        let children: Vec<(&dyn Widget, &BoxFragment)> = self
+            .children
+            .iter()
+            .zip(&fragment.children)
+            .filter_map(|(child, child_fragment)| match child_fragment {
+                Fragment::Box(child_fragment) => Some((child.as_ref(), child_fragment)),
+                Fragment::Text(_) => None,
+            })
+            .collect();
+        for is_gutter in [false, true] {
+            for &(child, child_fragment) in &children {
+                if (child_fragment.kind == BoxKind::Gutter) == is_gutter {
+                    child.paint(child_fragment, builder, containing_block);
+                }
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: &WidgetEvent) {
+        for child in &mut self.children {
+            child.handle_event(event);
+        }
+    }
+
+    fn height(&self) -> Au {
+        let heights = self.children.iter().map(|child| child.height());
+        match self.direction {
+            Direction::Column => heights.fold(Au(0), |sum, height| sum + height),
+            Direction::Row => heights.max().unwrap_or(Au(0)),
+        }
+    }
+
+    fn line_offset(&self, buffer: &Rc<RefCell<Buffer>>, line_idx: usize) -> Option<Au> {
+        let mut offset = Au(0);
+        for child in &self.children {
+            if let Some(line_offset) = child.line_offset(buffer, line_idx) {
+                return Some(offset + line_offset);
+            }
+            if self.direction == Direction::Column {
+                offset += child.height();
+            }
+        }
+        None
+    }
+}
+
+/// A box at the top left of its containing block, filling it, with no children.
+fn empty_box(size: Size<Au>) -> BoxFragment {
+    BoxFragment {
+        rect: Rect {
+            origin: super::Point { i: Au(0), b: Au(0) },
+            size,
+        },
+        margin: Sides::<Au>::zero(),
+        children: Vec::new(),
+        kind: BoxKind::Content,
+    }
+}
+
+/// Widen `fragment` to its children, since lines that are not wrapped may be
+/// wider than the containing block.
+fn fit_children(fragment: &mut BoxFragment) {
+    for child in &fragment.children {
+        let rect = match child {
+            Fragment::Text(text_fragment) => &text_fragment.rect,
+            Fragment::Box(box_fragment) => &box_fragment.rect,
+        };
+        fragment.rect.size.width = fragment.rect.size.width.max(rect.origin.i + rect.size.width);
+    }
+}
+
+pub fn layout_root(root: &Block, context: &mut LayoutContext, viewport_size: Size<Au>, visible: Range<Au>) -> BoxFragment {
+    root.layout(context, viewport_size, visible)
 }
 
-pub fn layout_root(root: &Block, context: &mut LayoutContext, viewport_size: Size<Au>) -> BoxFragment {
-    root.layout(context, viewport_size)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_caret_token_is_the_run_of_punctuation_or_word_chars_it_touches() {
+        let text = "a -> b == c";
+        // Inside, at the start of and at the end of an operator.
+        assert_eq!(caret_token(text, 3), Some(2..4));
+        assert_eq!(caret_token(text, 2), Some(2..4));
+        assert_eq!(caret_token(text, 4), Some(2..4));
+        assert_eq!(caret_token(text, 8), Some(7..9));
+        // Beside an operator only across whitespace.
+        assert_eq!(caret_token(text, 1), Some(0..1));
+        assert_eq!(caret_token(text, 5), Some(5..6));
+        assert_eq!(caret_token("a  b", 2), None);
+        assert_eq!(caret_token(text, 12), None);
+    }
+
+    #[test]
+    fn a_caret_between_a_word_and_an_operator_touches_both() {
+        assert_eq!(caret_token("x==y", 1), Some(0..3));
+        assert_eq!(caret_token("x==y", 3), Some(1..4));
+        assert_eq!(caret_token("x==y", 2), Some(1..3));
+    }
 }
@@ -8,18 +8,30 @@ extern crate webrender_api;
 extern crate winit;
 
 mod app;
+mod buffer;
+mod clipboard;
 mod compositor;
+mod editor;
+mod highlight;
 mod layout;
+mod resources;
+mod syntax;
+mod theme;
 // mod layout_thread;
 // mod widget;
 
+use std::path::PathBuf;
+
 use winit::event_loop::EventLoop;
 
 use crate::app::App;
 
-fn main() {
-    let event_loop = EventLoop::new().unwrap();
-    let mut app = App::new();
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args_os().nth(1).map(PathBuf::from);
+
+    let event_loop = EventLoop::new()?;
+    let mut app = App::new(path)?;
 
-    event_loop.run_app(&mut app).unwrap();
+    event_loop.run_app(&mut app)?;
+    Ok(())
 }
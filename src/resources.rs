@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+
+/// The directory of the files bundled with the editor: `resources` beside the
+/// executable or in a directory above it, which for builds under `target` is
+/// the source tree's. Falls back to the source tree the editor was built from,
+/// never to the working directory.
+pub fn resource_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.ancestors()
+                .skip(1)
+                .map(|dir| dir.join("resources"))
+                .find(|dir| dir.is_dir())
+        })
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources"))
+}
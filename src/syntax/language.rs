@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use tree_sitter::Language;
+
+/// A tree-sitter grammar bundled with the editor, and the files it is for.
+pub struct LanguageConfig {
+    pub name: &'static str,
+    /// File extensions, or whole file names for files without one.
+    pub extensions: &'static [&'static str],
+    pub grammar: fn() -> Language,
+    /// Query capturing nodes with names like `keyword` or `function.method`.
+    pub highlights: &'static str,
+}
+
+const LANGUAGES: &[LanguageConfig] = &[
+    LanguageConfig {
+        name: "Rust",
+        extensions: &["rs"],
+        grammar: || tree_sitter_rust::LANGUAGE.into(),
+        highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+    },
+    LanguageConfig {
+        name: "Python",
+        extensions: &["py", "pyi", "pyw"],
+        grammar: || tree_sitter_python::LANGUAGE.into(),
+        highlights: tree_sitter_python::HIGHLIGHTS_QUERY,
+    },
+    LanguageConfig {
+        name: "JSON",
+        extensions: &["json", "jsonc", ".prettierrc", ".eslintrc"],
+        grammar: || tree_sitter_json::LANGUAGE.into(),
+        highlights: tree_sitter_json::HIGHLIGHTS_QUERY,
+    },
+];
+
+/// The bundled grammar for the file at `path`, if there is one.
+pub fn language_for_path(path: &Path) -> Option<&'static LanguageConfig> {
+    let name = path.extension().or(path.file_name())?.to_str()?;
+    LANGUAGES.iter().find(|language| language.extensions.contains(&name))
+}
@@ -0,0 +1,246 @@
+pub mod language;
+mod structure;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Context;
+use syntect::highlighting::{Highlighter, Theme as SyntaxTheme};
+use syntect::parsing::Scope;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+use webrender_api::ColorF;
+
+use crate::buffer::edits::{Edit, TextPosition};
+use crate::buffer::{Buffer, Snapshot};
+use crate::highlight::{span_style, Highlight};
+use crate::layout::widget::{Span, SpanStyle};
+use crate::theme::Theme;
+
+use self::language::LanguageConfig;
+
+/// Scopes of the theme that tree-sitter capture names are styled as, by the
+/// longest dotted prefix of the capture name.
+const CAPTURE_SCOPES: &[(&str, &str)] = &[
+    ("attribute", "entity.other.attribute-name"),
+    ("comment", "comment"),
+    ("constant", "constant.other"),
+    ("constant.builtin", "constant.language"),
+    ("constructor", "entity.name.type"),
+    ("escape", "constant.character.escape"),
+    ("function", "entity.name.function"),
+    ("keyword", "keyword"),
+    ("label", "entity.name.label"),
+    ("number", "constant.numeric"),
+    ("operator", "keyword.operator"),
+    ("property", "variable.other.member"),
+    ("punctuation", "punctuation"),
+    ("string", "string"),
+    ("string.special.key", "support.type.property-name"),
+    ("type", "entity.name.type"),
+    ("type.builtin", "storage.type"),
+    ("variable.builtin", "variable.language"),
+    ("variable.parameter", "variable.parameter"),
+];
+
+/// A parse tree of a buffer, kept up to date by reparsing only what each
+/// edit changed.
+///
+/// Besides styling the text by its syntax, the tree knows which ranges of the
+/// text are syntax nodes, for growing the selection, coloring brackets by
+/// how deeply they nest and folding blocks away.
+pub struct SyntaxTree {
+    parser: Parser,
+    tree: Option<Tree>,
+    highlights: Query,
+    /// Style of each capture of `highlights`, if the theme styles it.
+    capture_styles: Vec<Option<SpanStyle>>,
+    /// Colors of brackets by how deeply they nest.
+    bracket_colors: Vec<ColorF>,
+    /// Revision of the buffer `tree` was parsed from.
+    revision: u64,
+    /// Start bytes of the nodes that are folded.
+    folded: Vec<usize>,
+}
+
+impl SyntaxTree {
+    /// Parse `buffer` with the grammar of `language`, to style it with `theme`.
+    pub fn new(language: &LanguageConfig, buffer: &Buffer, theme: &Theme) -> anyhow::Result<SyntaxTree> {
+        let grammar = (language.grammar)();
+        let mut parser = Parser::new();
+        parser
+            .set_language(&grammar)
+            .with_context(|| format!("the {} grammar is not supported", language.name))?;
+        let highlights = Query::new(&grammar, language.highlights)
+            .with_context(|| format!("invalid highlights query for {}", language.name))?;
+        let capture_styles = capture_styles(&highlights, &theme.syntax);
+        let mut syntax_tree = SyntaxTree {
+            parser,
+            tree: None,
+            highlights,
+            capture_styles,
+            bracket_colors: theme.brackets.clone(),
+            revision: buffer.revision(),
+            folded: Vec::new(),
+        };
+        syntax_tree.parse(&buffer.snapshot());
+        Ok(syntax_tree)
+    }
+
+    /// Catch up with the edits made to `buffer` since the last update,
+    /// reusing the parts of the old tree they did not touch.
+    pub fn update(&mut self, buffer: &Buffer) {
+        if self.revision == buffer.revision() {
+            return;
+        }
+        match buffer.edits_since(self.revision) {
+            Some(edits) => {
+                for edit in edits {
+                    if let Some(tree) = &mut self.tree {
+                        tree.edit(&input_edit(edit));
+                    }
+                    // Folds inside the edited text go, those after it move.
+                    self.folded.retain_mut(|start| {
+                        if *start >= edit.old_end_byte {
+                            *start = *start - edit.old_end_byte + edit.new_end_byte;
+                            true
+                        } else {
+                            *start < edit.start_byte
+                        }
+                    });
+                }
+            }
+            None => {
+                self.tree = None;
+                self.folded.clear();
+            }
+        }
+        self.revision = buffer.revision();
+        self.parse(&buffer.snapshot());
+    }
+
+    fn parse(&mut self, snapshot: &Snapshot) {
+        let mut read = |byte_idx: usize, _: Point| snapshot.chunk_at_byte(byte_idx).as_bytes();
+        self.tree = self.parser.parse_with_options(&mut read, self.tree.as_ref(), None);
+    }
+
+    /// The style of each byte of a line from the highlight captures, with
+    /// smaller nodes styled over the nodes containing them, and earlier
+    /// patterns winning between captures of the same node.
+    fn byte_styles(&self, tree: &Tree, snapshot: &Snapshot, line_idx: usize) -> Vec<Option<SpanStyle>> {
+        let line_start = snapshot.line_to_byte(line_idx);
+        let line_len = snapshot.line_text(line_idx).len();
+        // The capture styling each byte of the line, with the length of its node.
+        let mut captures: Vec<Option<(usize, u32)>> = vec![None; line_len];
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(line_start..line_start + line_len);
+        let text = |node: Node| snapshot.byte_slice(node.byte_range()).chunks().map(str::as_bytes);
+        let mut matches = cursor.matches(&self.highlights, tree.root_node(), text);
+        while let Some(query_match) = matches.next() {
+            for capture in query_match.captures {
+                if self.capture_styles[capture.index as usize].is_none() {
+                    continue;
+                }
+                let range = capture.node.byte_range();
+                let node_len = range.len();
+                let start = range.start.max(line_start) - line_start;
+                let end = range.end.min(line_start + line_len).saturating_sub(line_start);
+                for slot in captures.iter_mut().take(end).skip(start) {
+                    if slot.is_none_or(|(len, _)| node_len < len) {
+                        *slot = Some((node_len, capture.index));
+                    }
+                }
+            }
+        }
+        captures
+            .into_iter()
+            .map(|capture| capture.and_then(|(_, index)| self.capture_styles[index as usize].clone()))
+            .collect()
+    }
+}
+
+impl Highlight for Rc<RefCell<SyntaxTree>> {
+    fn update(&mut self, buffer: &Buffer) {
+        self.borrow_mut().update(buffer);
+    }
+
+    fn line_spans(&mut self, snapshot: &Snapshot, line_idx: usize) -> Vec<Span> {
+        let syntax_tree = self.borrow();
+        let Some(tree) = &syntax_tree.tree else {
+            return Vec::new();
+        };
+        let mut styles = syntax_tree.byte_styles(tree, snapshot, line_idx);
+        let line_start = snapshot.line_to_byte(line_idx);
+        for (range, depth) in structure::brackets(tree, line_start..line_start + styles.len()) {
+            let Some(&color) = syntax_tree.bracket_colors.get(depth % syntax_tree.bracket_colors.len().max(1)) else {
+                break;
+            };
+            let style = SpanStyle {
+                color: Some(color),
+                ..SpanStyle::default()
+            };
+            for slot in &mut styles[range.start - line_start..range.end - line_start] {
+                *slot = Some(style.clone());
+            }
+        }
+
+        // Turn the style of each byte into spans of chars.
+        let mut spans: Vec<Span> = Vec::new();
+        let text = snapshot.line_text(line_idx);
+        for (offset, (byte, _)) in (snapshot.line_to_char(line_idx)..).zip(text.char_indices()) {
+            if let Some(style) = &styles[byte] {
+                match spans.last_mut() {
+                    Some(span) if span.range.end == offset && &span.style == style => span.range.end += 1,
+                    _ => spans.push(Span {
+                        range: offset..offset + 1,
+                        style: style.clone(),
+                    }),
+                }
+            }
+        }
+        spans
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        let mut syntax_tree = self.borrow_mut();
+        syntax_tree.capture_styles = capture_styles(&syntax_tree.highlights, &theme.syntax);
+        syntax_tree.bracket_colors = theme.brackets.clone();
+    }
+}
+
+/// The style of each capture of `query` in `theme`.
+fn capture_styles(query: &Query, theme: &SyntaxTheme) -> Vec<Option<SpanStyle>> {
+    let highlighter = Highlighter::new(theme);
+    let default_style = highlighter.get_default();
+    query
+        .capture_names()
+        .iter()
+        .map(|name| {
+            let scope = capture_scope(name)?;
+            let style = highlighter.style_for_stack(&[Scope::new(scope).ok()?]);
+            (style != default_style).then(|| span_style(style))
+        })
+        .collect()
+}
+
+fn capture_scope(name: &str) -> Option<&'static str> {
+    let mut name = name;
+    loop {
+        if let Some((_, scope)) = CAPTURE_SCOPES.iter().find(|(capture, _)| *capture == name) {
+            return Some(scope);
+        }
+        name = &name[..name.rfind('.')?];
+    }
+}
+
+fn input_edit(edit: &Edit) -> InputEdit {
+    let point = |position: TextPosition| Point::new(position.line, position.column);
+    InputEdit {
+        start_byte: edit.start_byte,
+        old_end_byte: edit.old_end_byte,
+        new_end_byte: edit.new_end_byte,
+        start_position: point(edit.start),
+        old_end_position: point(edit.old_end),
+        new_end_position: point(edit.new_end),
+    }
+}
@@ -0,0 +1,369 @@
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree, TreeCursor};
+
+use crate::buffer::Snapshot;
+
+use super::SyntaxTree;
+
+const BRACKET_PAIRS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+
+impl SyntaxTree {
+    /// The range of the smallest syntax node strictly containing the chars in
+    /// `range`, to grow a selection by.
+    pub fn expand(&self, snapshot: &Snapshot, range: Range<usize>) -> Option<Range<usize>> {
+        let tree = self.tree.as_ref()?;
+        let start = snapshot.char_to_byte(range.start);
+        let end = snapshot.char_to_byte(range.end);
+        let mut node = tree.root_node().descendant_for_byte_range(start, end)?;
+        while node.start_byte() == start && node.end_byte() == end {
+            node = node.parent()?;
+        }
+        Some(snapshot.byte_to_char(node.start_byte())..snapshot.byte_to_char(node.end_byte()))
+    }
+
+    /// Fold the block starting on `line`, or unfold it if it is folded.
+    /// Returns whether anything changed.
+    pub fn toggle_fold(&mut self, snapshot: &Snapshot, line: usize) -> bool {
+        let folds = self.folded.len();
+        self.folded.retain(|&start| snapshot.byte_to_line(start) != line);
+        if self.folded.len() != folds {
+            return true;
+        }
+        let Some((node, _)) = self.fold_at(snapshot, line) else {
+            return false;
+        };
+        self.folded.push(node.start_byte());
+        true
+    }
+
+    /// Unfold the blocks hiding `line`, returning whether there were any.
+    pub fn unfold_line(&mut self, snapshot: &Snapshot, line: usize) -> bool {
+        let hiding: Vec<usize> = self
+            .folds(snapshot)
+            .into_iter()
+            .filter(|(_, lines)| lines.contains(&line))
+            .map(|(start, _)| start)
+            .collect();
+        self.folded.retain(|start| !hiding.contains(start));
+        !hiding.is_empty()
+    }
+
+    /// The lines hidden by folds, as ranges that may nest.
+    pub fn folded_lines(&self, snapshot: &Snapshot) -> Vec<Range<usize>> {
+        self.folds(snapshot).into_iter().map(|(_, lines)| lines).collect()
+    }
+
+    /// The start of each folded node that is still foldable, and the lines
+    /// it hides.
+    fn folds(&self, snapshot: &Snapshot) -> Vec<(usize, Range<usize>)> {
+        self.folded
+            .iter()
+            .filter_map(|&start| {
+                let (node, lines) = self.fold_at(snapshot, snapshot.byte_to_line(start))?;
+                (node.start_byte() == start).then_some((start, lines))
+            })
+            .collect()
+    }
+
+    /// The largest node starting on `line` that spans several lines, and the
+    /// lines folding it hides: all but the first, and the last too unless it
+    /// closes a bracket opened on `line`, so that the closing bracket stays in
+    /// view. The body of a node starting on an earlier line is folded from
+    /// there, not from its own first line.
+    fn fold_at(&self, snapshot: &Snapshot, line: usize) -> Option<(Node<'_>, Range<usize>)> {
+        let tree = self.tree.as_ref()?;
+        let line_start = snapshot.line_to_byte(line);
+        let line_end = line_start + snapshot.line_text(line).len();
+
+        let mut best: Option<(Node, Range<usize>)> = None;
+        let mut cursor = tree.walk();
+        visit(&mut cursor, line_start..line_end.max(line_start + 1), &mut |node| {
+            if !node.is_named() || node.start_byte() < line_start {
+                return;
+            }
+            let Some(parent) = node.parent() else {
+                return;
+            };
+            let is_body = parent.child_by_field_name("body").is_some_and(|body| body.id() == node.id());
+            if is_body && parent.start_byte() < line_start {
+                return;
+            }
+            let last_line = snapshot.byte_to_line(node.end_byte());
+            let end = if closes_bracket_on(node, snapshot, line) { last_line } else { last_line + 1 };
+            if end <= line + 1 {
+                return;
+            }
+            if best.as_ref().is_none_or(|(_, lines)| end > lines.end) {
+                best = Some((node, line + 1..end));
+            }
+        });
+        best
+    }
+}
+
+/// The brackets within `range` that pair up in the tree, with how many
+/// bracketed nodes they are nested in.
+pub fn brackets(tree: &Tree, range: Range<usize>) -> Vec<(Range<usize>, usize)> {
+    let mut brackets = Vec::new();
+    let mut cursor = tree.walk();
+    visit(&mut cursor, range.clone(), &mut |node| {
+        let is_bracket = BRACKET_PAIRS.iter().any(|(open, close)| node.kind() == *open || node.kind() == *close);
+        if node.is_named() || node.is_missing() || !is_bracket || !node.parent().is_some_and(is_bracketed) {
+            return;
+        }
+        let node_range = node.byte_range();
+        if node_range.start < range.start || node_range.end > range.end {
+            return;
+        }
+        let mut depth = 0;
+        let mut ancestor = node.parent().and_then(|parent| parent.parent());
+        while let Some(node) = ancestor {
+            depth += is_bracketed(node) as usize;
+            ancestor = node.parent();
+        }
+        brackets.push((node_range, depth));
+    });
+    brackets
+}
+
+/// Whether `node` is enclosed in a pair of brackets, like a block or an
+/// argument list.
+fn is_bracketed(node: Node) -> bool {
+    let (Some(first), Some(last)) = (node.child(0), node.child(node.child_count().saturating_sub(1))) else {
+        return false;
+    };
+    first.id() != last.id() &&
+        !last.is_missing() &&
+        BRACKET_PAIRS.iter().any(|(open, close)| first.kind() == *open && last.kind() == *close)
+}
+
+/// Whether the last token of `node` closes a bracket opened on `line`.
+fn closes_bracket_on(node: Node, snapshot: &Snapshot, line: usize) -> bool {
+    let mut last = node;
+    while let Some(child) = last.child(last.child_count().saturating_sub(1)) {
+        last = child;
+    }
+    last.parent()
+        .filter(|&parent| is_bracketed(parent))
+        .and_then(|parent| parent.child(0))
+        .is_some_and(|open| snapshot.byte_to_line(open.start_byte()) == line)
+}
+
+/// Call `f` on every node intersecting `range`, parents before children,
+/// without descending into the rest of the tree.
+fn visit<'tree>(cursor: &mut TreeCursor<'tree>, range: Range<usize>, f: &mut impl FnMut(Node<'tree>)) {
+    let node = cursor.node();
+    if node.end_byte() <= range.start || node.start_byte() >= range.end {
+        return;
+    }
+    f(node);
+    if cursor.goto_first_child_for_byte(range.start).is_none() {
+        return;
+    }
+    loop {
+        if cursor.node().start_byte() >= range.end {
+            break;
+        }
+        visit(cursor, range.clone(), f);
+        if !cursor.goto_next_sibling() {
+            break;
+        }
+    }
+    cursor.goto_parent();
+}
+
+#[cfg(test)]
+// Folded lines are compared as lists of ranges, which may hold just one.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::path::Path;
+
+    use crate::buffer::Buffer;
+    use crate::buffer::history::EditKind;
+    use crate::editor::Selection;
+    use crate::syntax::language::language_for_path;
+    use crate::theme::Theme;
+
+    use super::*;
+
+    const RUST: &str = include_str!("../../tests/samples/sample.rs");
+    const PYTHON: &str = include_str!("../../tests/samples/sample.py");
+    const JSON: &str = include_str!("../../tests/samples/sample.json");
+
+    fn parse(file_name: &str, text: &str) -> (Buffer, SyntaxTree) {
+        let buffer = Buffer::from(text);
+        let language = language_for_path(Path::new(file_name)).unwrap();
+        let tree = SyntaxTree::new(language, &buffer, &Theme::light()).unwrap();
+        (buffer, tree)
+    }
+
+    fn insert(buffer: &mut Buffer, tree: &mut SyntaxTree, char_idx: usize, text: &str) {
+        let selection = Selection::caret(char_idx);
+        buffer.edit(char_idx..char_idx, text, EditKind::Other, selection, selection);
+        tree.update(buffer);
+    }
+
+    /// The text of each selection that expanding from the start of the
+    /// first `needle` in the buffer goes through.
+    fn expansions(buffer: &Buffer, tree: &SyntaxTree, needle: &str) -> Vec<String> {
+        let snapshot = buffer.snapshot();
+        let text = buffer.to_string();
+        let start = buffer.byte_to_char(text.find(needle).unwrap());
+        let mut range = start..start;
+        let mut texts = Vec::new();
+        while let Some(expanded) = tree.expand(&snapshot, range) {
+            texts.push(snapshot.slice(expanded.clone()).to_string());
+            range = expanded;
+        }
+        texts
+    }
+
+    /// The lines each line's fold would hide, for the lines with one.
+    fn fold_ranges(buffer: &Buffer, tree: &SyntaxTree) -> Vec<(usize, Range<usize>)> {
+        let snapshot = buffer.snapshot();
+        (0..snapshot.len_lines())
+            .filter_map(|line| Some((line, tree.fold_at(&snapshot, line)?.1)))
+            .collect()
+    }
+
+    /// The bracket pairs of the whole buffer, as the brackets, the lines they
+    /// are on and their depth, in the order they open.
+    fn bracket_pairs(buffer: &Buffer, tree: &SyntaxTree) -> Vec<(String, usize, usize, usize)> {
+        let snapshot = buffer.snapshot();
+        let text = buffer.to_string();
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        let mut pairs = Vec::new();
+        for (range, depth) in brackets(tree.tree.as_ref().unwrap(), 0..text.len()) {
+            if BRACKET_PAIRS.iter().any(|(open, _)| text[range.clone()] == **open) {
+                open.push((range.start, depth));
+                pairs.push((String::new(), 0, 0, depth));
+                continue;
+            }
+            let (start, open_depth) = open.pop().expect("closing bracket without an opening one");
+            assert_eq!(open_depth, depth, "brackets of a pair at different depths");
+            let pair = pairs.iter_mut().rev().find(|pair| pair.0.is_empty()).unwrap();
+            *pair = (
+                format!("{}{}", &text[start..start + 1], &text[range.clone()]),
+                snapshot.byte_to_line(start),
+                snapshot.byte_to_line(range.start),
+                depth,
+            );
+        }
+        assert!(open.is_empty(), "opening bracket without a closing one");
+        pairs
+    }
+
+    #[test]
+    fn expand_grows_through_enclosing_nodes() {
+        let (buffer, tree) = parse("sample.rs", RUST);
+        let steps = expansions(&buffer, &tree, "values");
+        assert_eq!(steps[..2], ["values", "let values = [1, 2, 3];"]);
+        assert!(steps[2].starts_with("{\n") && steps[2].ends_with("\n}"));
+        assert!(steps[3].starts_with("fn main() {"));
+        assert_eq!(steps.last().unwrap(), RUST);
+
+        let (buffer, tree) = parse("sample.py", PYTHON);
+        let steps = expansions(&buffer, &tree, "values");
+        assert_eq!(steps[..2], ["values", "(values, factor)"]);
+        assert!(steps[2].starts_with("def scale") && steps[2].ends_with("return result"));
+
+        let (buffer, tree) = parse("sample.json", JSON);
+        let steps = expansions(&buffer, &tree, "values");
+        assert_eq!(steps[..3], ["values", "\"values\"", "\"values\": [1, 2, 3]"]);
+    }
+
+    #[test]
+    fn folds_keep_the_header_and_closing_bracket_in_view() {
+        let (buffer, tree) = parse("sample.rs", RUST);
+        assert_eq!(fold_ranges(&buffer, &tree), [(0, 1..5), (2, 3..4), (7, 8..9)]);
+
+        // Blocks without brackets hide their last line too, and bodies fold
+        // from the line of their header only.
+        let (buffer, tree) = parse("sample.py", PYTHON);
+        assert_eq!(fold_ranges(&buffer, &tree), [(0, 1..5), (2, 3..4)]);
+
+        let (buffer, tree) = parse("sample.json", JSON);
+        assert_eq!(fold_ranges(&buffer, &tree), [(0, 1..7), (3, 4..6)]);
+    }
+
+    #[test]
+    fn toggling_folds() {
+        let (buffer, mut tree) = parse("sample.rs", RUST);
+        let snapshot = buffer.snapshot();
+        assert!(!tree.toggle_fold(&snapshot, 1));
+        assert!(tree.toggle_fold(&snapshot, 2));
+        assert!(tree.toggle_fold(&snapshot, 0));
+        assert_eq!(tree.folded_lines(&snapshot), [3..4, 1..5]);
+        assert!(tree.unfold_line(&snapshot, 3));
+        assert!(tree.folded_lines(&snapshot).is_empty());
+        assert!(tree.toggle_fold(&snapshot, 7));
+        assert!(tree.toggle_fold(&snapshot, 7));
+        assert!(tree.folded_lines(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn brackets_pair_up_by_depth() {
+        let (buffer, tree) = parse("sample.rs", RUST);
+        let pair = |brackets: &str, open, close, depth| (brackets.to_owned(), open, close, depth);
+        assert_eq!(bracket_pairs(&buffer, &tree), [
+            pair("()", 0, 0, 0),
+            pair("{}", 0, 5, 0),
+            pair("[]", 1, 1, 1),
+            pair("()", 2, 2, 1),
+            pair("{}", 2, 4, 1),
+            pair("()", 3, 3, 2),
+            pair("()", 3, 3, 3),
+            pair("()", 7, 7, 0),
+            pair("{}", 7, 9, 0),
+        ]);
+
+        let (buffer, tree) = parse("sample.json", JSON);
+        assert_eq!(bracket_pairs(&buffer, &tree), [
+            pair("{}", 0, 7, 0),
+            pair("[]", 2, 2, 1),
+            pair("{}", 3, 6, 1),
+            pair("{}", 4, 4, 2),
+            pair("[]", 5, 5, 2),
+            pair("[]", 5, 5, 3),
+            pair("[]", 5, 5, 3),
+        ]);
+    }
+
+    #[test]
+    fn edits_are_followed_incrementally() {
+        let (mut buffer, mut tree) = parse("sample.rs", RUST);
+        assert!(tree.toggle_fold(&buffer.snapshot(), 2));
+        insert(&mut buffer, &mut tree, 0, "// A sample.\n");
+
+        // Everything moves down a line.
+        let snapshot = buffer.snapshot();
+        assert_eq!(tree.folded_lines(&snapshot), [4..5]);
+        assert_eq!(fold_ranges(&buffer, &tree), [(1, 2..6), (3, 4..5), (8, 9..10)]);
+        let pairs = bracket_pairs(&buffer, &tree);
+        assert_eq!(pairs.len(), 9);
+        assert_eq!(pairs[1], ("{}".to_owned(), 1, 6, 0));
+        assert_eq!(expansions(&buffer, &tree, "values")[..2], ["values", "let values = [1, 2, 3];"]);
+
+        // A line added to a block grows its fold.
+        let (mut buffer, mut tree) = parse("sample.py", PYTHON);
+        assert!(tree.toggle_fold(&buffer.snapshot(), 2));
+        let line_end = buffer.line_to_char(4);
+        insert(&mut buffer, &mut tree, line_end, "        result.append(-value)\n");
+        assert_eq!(tree.folded_lines(&buffer.snapshot()), [3..5]);
+        assert_eq!(fold_ranges(&buffer, &tree), [(0, 1..6), (2, 3..5)]);
+        assert_eq!(expansions(&buffer, &tree, "-value")[..3], ["-", "-value", "(-value)"]);
+
+        // New brackets nest in the old ones.
+        let (mut buffer, mut tree) = parse("sample.json", JSON);
+        let list = buffer.byte_to_char(JSON.find("[[").unwrap()) + 1;
+        insert(&mut buffer, &mut tree, list, "[[0]], ");
+        let pairs = bracket_pairs(&buffer, &tree);
+        assert_eq!(pairs.len(), 9);
+        assert_eq!(pairs[5..8], [
+            ("[]".to_owned(), 5, 5, 3),
+            ("[]".to_owned(), 5, 5, 4),
+            ("[]".to_owned(), 5, 5, 3),
+        ]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde_json::Value;
+use syntect::highlighting::{
+    Color, FontStyle, ScopeSelectors, StyleModifier, Theme as SyntaxTheme, ThemeItem, ThemeSet, ThemeSettings,
+};
+use webrender_api::ColorF;
+
+use crate::highlight::config_dir;
+use crate::resources::resource_dir;
+
+/// Themes bundled with the editor, in its resource directory.
+const BUNDLED_THEMES: &str = "themes";
+
+/// The colors of the editor: its background, the text and what is drawn
+/// over it, the gutter, and the styles of syntax scopes.
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    pub background: ColorF,
+    pub foreground: ColorF,
+    pub selection: ColorF,
+    pub caret: ColorF,
+    pub gutter_background: ColorF,
+    /// Color of line numbers.
+    pub gutter_foreground: ColorF,
+    /// Color of the number of the line with the caret.
+    pub gutter_active_foreground: ColorF,
+    /// Colors of brackets from the outermost pair in, repeating for pairs
+    /// nested deeper.
+    pub brackets: Vec<ColorF>,
+    /// Styles of syntax scopes, as in a TextMate theme.
+    pub syntax: SyntaxTheme,
+}
+
+impl Theme {
+    pub fn light() -> Theme {
+        let syntax_rules = vec![
+            rule("comment", 0x6a737d, FontStyle::ITALIC),
+            rule("string", 0x032f62, FontStyle::empty()),
+            rule("constant.numeric, constant.language, constant.character", 0x005cc5, FontStyle::empty()),
+            rule("keyword, storage", 0xd73a49, FontStyle::empty()),
+            rule("storage.type, support.type, entity.name.type", 0x6f42c1, FontStyle::empty()),
+            rule("entity.name.function, support.function", 0x6f42c1, FontStyle::BOLD),
+            rule("variable.parameter", 0xe36209, FontStyle::empty()),
+            rule("invalid", 0xb31d28, FontStyle::BOLD),
+        ];
+        Theme {
+            name: "Light".to_owned(),
+            background: ColorF::WHITE,
+            foreground: ColorF::BLACK,
+            selection: ColorF::new(0.7, 0.8, 1.0, 1.0),
+            caret: ColorF::BLACK,
+            gutter_background: ColorF::new(0.97, 0.97, 0.97, 1.0),
+            gutter_foreground: ColorF::new(0.6, 0.6, 0.6, 1.0),
+            gutter_active_foreground: ColorF::new(0.2, 0.2, 0.2, 1.0),
+            brackets: vec![rgb(0xb08800), rgb(0x8250df), rgb(0x0969da)],
+            syntax: syntax_theme(ColorF::BLACK, ColorF::WHITE, syntax_rules),
+        }
+    }
+
+    pub fn dark() -> Theme {
+        let foreground = rgb(0xd4d4d4);
+        let background = rgb(0x1e1e1e);
+        let syntax_rules = vec![
+            rule("comment", 0x6a9955, FontStyle::ITALIC),
+            rule("string", 0xce9178, FontStyle::empty()),
+            rule("constant.numeric, constant.language, constant.character", 0xb5cea8, FontStyle::empty()),
+            rule("keyword, storage", 0x569cd6, FontStyle::empty()),
+            rule("storage.type, support.type, entity.name.type", 0x4ec9b0, FontStyle::empty()),
+            rule("entity.name.function, support.function", 0xdcdcaa, FontStyle::empty()),
+            rule("variable.parameter", 0x9cdcfe, FontStyle::empty()),
+            rule("invalid", 0xf44747, FontStyle::BOLD),
+        ];
+        Theme {
+            name: "Dark".to_owned(),
+            background,
+            foreground,
+            selection: rgb(0x264f78),
+            caret: rgb(0xaeafad),
+            gutter_background: background,
+            gutter_foreground: rgb(0x858585),
+            gutter_active_foreground: rgb(0xc6c6c6),
+            brackets: vec![rgb(0xffd700), rgb(0xda70d6), rgb(0x179fff)],
+            syntax: syntax_theme(foreground, background, syntax_rules),
+        }
+    }
+
+    /// Load a VS Code color theme (`.json`) or a TextMate theme (`.tmTheme`).
+    pub fn load(path: &Path) -> anyhow::Result<Theme> {
+        let is_tm_theme = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("tmTheme"));
+        if is_tm_theme {
+            Theme::load_tm_theme(path)
+        } else {
+            Theme::load_vscode(path, &mut Vec::new())
+        }
+    }
+
+    fn load_tm_theme(path: &Path) -> anyhow::Result<Theme> {
+        let syntax = ThemeSet::get_theme(path).with_context(|| format!("failed to load theme {}", path.display()))?;
+        let settings = &syntax.settings;
+        // Colors the theme leaves out come from the built-in theme closest
+        // to its background.
+        let is_dark = settings.background.is_some_and(|color| luminance(to_color(color)) < 0.5);
+        let mut theme = if is_dark { Theme::dark() } else { Theme::light() };
+        let set = |field: &mut ColorF, color: Option<Color>| {
+            if let Some(color) = color {
+                *field = to_color(color);
+            }
+        };
+        set(&mut theme.background, settings.background);
+        set(&mut theme.foreground, settings.foreground);
+        set(&mut theme.selection, settings.selection);
+        set(&mut theme.caret, settings.caret);
+        set(&mut theme.gutter_background, settings.gutter.or(settings.background));
+        set(&mut theme.gutter_foreground, settings.gutter_foreground);
+        // TextMate themes have no color for the current line's number, so it
+        // stands out in the text's color.
+        set(&mut theme.gutter_active_foreground, settings.foreground);
+        theme.name = syntax.name.clone().unwrap_or_else(|| file_stem(path));
+        theme.syntax = syntax;
+        Ok(theme)
+    }
+
+    /// Load a VS Code theme, given the themes that include it so far.
+    fn load_vscode(path: &Path, including: &mut Vec<PathBuf>) -> anyhow::Result<Theme> {
+        let canonical = fs::canonicalize(path).with_context(|| format!("failed to read theme {}", path.display()))?;
+        if including.contains(&canonical) {
+            bail!("theme {} includes itself", path.display());
+        }
+        including.push(canonical);
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read theme {}", path.display()))?;
+        // Theme files are usually JSON with comments and trailing commas.
+        let json: Value = serde_json::from_str(&strip_json_comments(&text))
+            .with_context(|| format!("failed to parse theme {}", path.display()))?;
+
+        // A theme may build on another one, given relative to it.
+        let mut theme = match json["include"].as_str() {
+            Some(include) => Theme::load_vscode(&path.parent().unwrap_or(Path::new("")).join(include), including)?,
+            None if json["type"] == "dark" || json["type"] == "hc" => Theme::dark(),
+            None => Theme::light(),
+        };
+        let inherits_rules = json["include"].is_string();
+        theme.name = json["name"].as_str().map_or_else(|| file_stem(path), str::to_owned);
+
+        let colors = &json["colors"];
+        let set = |field: &mut ColorF, key: &str| {
+            if let Some(color) = colors[key].as_str().and_then(parse_color) {
+                *field = color;
+            }
+        };
+        set(&mut theme.background, "editor.background");
+        set(&mut theme.foreground, "editor.foreground");
+        set(&mut theme.selection, "editor.selectionBackground");
+        set(&mut theme.caret, "editorCursor.foreground");
+        set(&mut theme.gutter_background, "editorGutter.background");
+        set(&mut theme.gutter_foreground, "editorLineNumber.foreground");
+        set(&mut theme.gutter_active_foreground, "editorLineNumber.activeForeground");
+        let brackets: Vec<ColorF> = (1..=6)
+            .filter_map(|i| colors[format!("editorBracketHighlight.foreground{i}")].as_str().and_then(parse_color))
+            .collect();
+        if !brackets.is_empty() {
+            theme.brackets = brackets;
+        }
+
+        if let Some(token_colors) = json["tokenColors"].as_array() {
+            if !inherits_rules {
+                theme.syntax.scopes.clear();
+            }
+            theme.syntax.scopes.extend(token_colors.iter().filter_map(token_color_rule));
+        }
+        theme.syntax.settings.foreground = Some(to_syntax_color(theme.foreground));
+        theme.syntax.settings.background = Some(to_syntax_color(theme.background));
+        Ok(theme)
+    }
+}
+
+/// The theme files bundled with the editor and in
+/// `$XDG_CONFIG_HOME/wedit/themes`.
+pub fn theme_paths() -> Vec<PathBuf> {
+    let mut dirs = vec![resource_dir().join(BUNDLED_THEMES)];
+    dirs.extend(config_dir().map(|config| config.join("themes")));
+    let mut paths = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|extension| {
+                    extension.eq_ignore_ascii_case("json") || extension.eq_ignore_ascii_case("tmTheme")
+                })
+            })
+            .collect();
+        files.sort();
+        paths.extend(files);
+    }
+    paths
+}
+
+/// A syntax rule from an entry of a VS Code theme's `tokenColors`.
+fn token_color_rule(token_color: &Value) -> Option<ThemeItem> {
+    let scope = match &token_color["scope"] {
+        Value::String(scope) => scope.clone(),
+        Value::Array(scopes) => scopes.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", "),
+        _ => return None,
+    };
+    let settings = &token_color["settings"];
+    let font_style = settings["fontStyle"].as_str().map(|font_style| {
+        font_style.split_whitespace().fold(FontStyle::empty(), |style, word| match word {
+            "bold" => style | FontStyle::BOLD,
+            "italic" => style | FontStyle::ITALIC,
+            "underline" => style | FontStyle::UNDERLINE,
+            _ => style,
+        })
+    });
+    Some(ThemeItem {
+        scope: scope.parse::<ScopeSelectors>().ok()?,
+        style: StyleModifier {
+            foreground: settings["foreground"].as_str().and_then(parse_color).map(to_syntax_color),
+            background: None,
+            font_style,
+        },
+    })
+}
+
+/// Remove the `//` and `/* */` comments and the trailing commas that VS Code
+/// allows in its JSON files.
+fn strip_json_comments(text: &str) -> String {
+    let mut json = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                json.push(c);
+                while let Some(c) = chars.next() {
+                    json.push(c);
+                    match c {
+                        '\\' => json.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '}' | ']' => {
+                let trimmed = json.trim_end().len();
+                if json[..trimmed].ends_with(',') {
+                    json.truncate(trimmed - 1);
+                }
+                json.push(c);
+            }
+            _ => json.push(c),
+        }
+    }
+    json
+}
+
+/// Parse a `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` color.
+fn parse_color(color: &str) -> Option<ColorF> {
+    let hex = color.strip_prefix('#')?;
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect::<Option<_>>()?;
+    let channels: Vec<u8> = match digits.len() {
+        3 | 4 => digits.iter().map(|d| d * 17).collect(),
+        6 | 8 => digits.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect(),
+        _ => return None,
+    };
+    let channel = |i: usize| channels.get(i).map_or(1.0, |&c| c as f32 / 255.0);
+    Some(ColorF::new(channel(0), channel(1), channel(2), channel(3)))
+}
+
+fn syntax_theme(foreground: ColorF, background: ColorF, scopes: Vec<ThemeItem>) -> SyntaxTheme {
+    SyntaxTheme {
+        settings: ThemeSettings {
+            foreground: Some(to_syntax_color(foreground)),
+            background: Some(to_syntax_color(background)),
+            ..ThemeSettings::default()
+        },
+        scopes,
+        ..SyntaxTheme::default()
+    }
+}
+
+fn rule(scope: &str, color: u32, font_style: FontStyle) -> ThemeItem {
+    ThemeItem {
+        scope: scope.parse().expect("valid scope selector"),
+        style: StyleModifier {
+            foreground: Some(to_syntax_color(rgb(color))),
+            background: None,
+            font_style: Some(font_style),
+        },
+    }
+}
+
+fn rgb(color: u32) -> ColorF {
+    let channel = |shift: u32| ((color >> shift) & 0xff) as f32 / 255.0;
+    ColorF::new(channel(16), channel(8), channel(0), 1.0)
+}
+
+pub fn to_color(color: Color) -> ColorF {
+    ColorF::new(
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0,
+        color.a as f32 / 255.0,
+    )
+}
+
+fn to_syntax_color(color: ColorF) -> Color {
+    let channel = |c: f32| (c * 255.0).round() as u8;
+    Color {
+        r: channel(color.r),
+        g: channel(color.g),
+        b: channel(color.b),
+        a: channel(color.a),
+    }
+}
+
+fn luminance(color: ColorF) -> f32 {
+    0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn themes_including_each_other_fail_to_load() {
+        let dir = std::env::temp_dir().join(format!("wedit-theme-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.json"), r#"{ "include": "./b.json" }"#).unwrap();
+        fs::write(dir.join("b.json"), r#"{ "include": "a.json" }"#).unwrap();
+        let error = Theme::load(&dir.join("a.json")).err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(format!("{error:#}").contains("includes itself"), "{error:#}");
+    }
+
+    #[test]
+    fn bundled_themes_load() {
+        // Not `theme_paths`, which also finds the user's themes.
+        let entries = fs::read_dir(resource_dir().join(BUNDLED_THEMES)).unwrap();
+        let paths: Vec<PathBuf> = entries.map(|entry| entry.unwrap().path()).collect();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let theme = Theme::load(&path).unwrap();
+            assert_eq!(theme.gutter_active_foreground.a, 1.0, "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,10 @@
+fn main() {
+    let values = [1, 2, 3];
+    for value in values.iter() {
+        print(add(*value, 1));
+    }
+}
+
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}